@@ -0,0 +1,174 @@
+use anyhow::{anyhow, bail, Result};
+use khronos_egl as egl;
+use std::ffi::c_void;
+
+use crate::capture::{Capture, CaptureBackend};
+use crate::format;
+use crate::render::EglContext;
+use crate::sway;
+use crate::wayland::WaylandConnection;
+
+/// Globals no mirror works without, and who offers them
+const REQUIRED_GLOBALS: &[(&str, &str)] = &[
+    ("wl_compositor", "core Wayland, offered by every compositor"),
+    (
+        "zwlr_layer_shell_v1",
+        "wlr-layer-shell, offered by sway and other wlroots-based compositors such as Hyprland, river and Wayfire, and by KDE Plasma, but not by GNOME",
+    ),
+];
+
+/// Capture protocols we know about, in order of preference
+const CAPTURE_BACKENDS: &[(&str, &str)] = &[
+    ("zwlr_export_dmabuf_manager_v1", "export-dmabuf (supported)"),
+    ("zwlr_screencopy_manager_v1", "wlr-screencopy (supported)"),
+    (
+        "ext_image_copy_capture_manager_v1",
+        "ext-image-copy-capture (not supported yet)",
+    ),
+];
+
+/// Optional protocols worth knowing about when triaging bug reports
+const OPTIONAL_PROTOCOLS: &[(&str, &str)] = &[
+    ("zwp_idle_inhibit_manager_v1", "idle-inhibit"),
+    ("wp_presentation", "presentation-time"),
+    ("wp_tearing_control_manager_v1", "tearing-control"),
+    ("wp_fractional_scale_manager_v1", "fractional-scale"),
+    ("wp_viewporter", "viewporter"),
+];
+
+fn yes_no(present: bool) -> &'static str {
+    if present {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Fail, naming what is missing, unless the compositor offers every global a
+/// mirror needs. Run before anything is changed, e.g. workspaces moved.
+pub fn check_required_globals(conn: &WaylandConnection) -> Result<()> {
+    let missing: Vec<String> = REQUIRED_GLOBALS
+        .iter()
+        .filter(|(interface, _)| !conn.has_global(interface))
+        .map(|(interface, offered_by)| format!("  {}: {}", interface, offered_by))
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "The compositor doesn't offer what sway-mirror needs to draw the mirror:\n{}\nNothing was changed.",
+            missing.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Print a paste-able report of what the compositor and driver support
+pub fn print_report(conn: &WaylandConnection) -> Result<()> {
+    println!("sway-mirror {}", env!("CARGO_PKG_VERSION"));
+
+    println!("\nCompositor:");
+    match sway::get_version() {
+        Ok(version) => println!("  {}", version),
+        Err(_) => println!(
+            "  unknown (not sway; XDG_CURRENT_DESKTOP={})",
+            std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default()
+        ),
+    }
+
+    println!("\nGlobals:");
+    let mut globals = conn.state.globals.clone();
+    globals.sort();
+    globals.dedup();
+    for (interface, version) in &globals {
+        println!("  {} v{}", interface, version);
+    }
+
+    println!("\nRequired protocols:");
+    for (interface, _) in REQUIRED_GLOBALS {
+        println!("  {}: {}", interface, yes_no(conn.has_global(interface)));
+    }
+
+    println!("\nCapture backends:");
+    for (interface, label) in CAPTURE_BACKENDS {
+        println!("  {}: {}", label, yes_no(conn.has_global(interface)));
+    }
+
+    println!("\nOptional protocols:");
+    for (interface, label) in OPTIONAL_PROTOCOLS {
+        println!("  {}: {}", label, yes_no(conn.has_global(interface)));
+    }
+
+    println!("\nEGL:");
+    let wayland_display = conn.connection.backend().display_ptr() as *mut c_void;
+    let mut egl_ctx = match EglContext::new(wayland_display, false) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            println!("  unavailable: {:#}", e);
+            return Ok(());
+        }
+    };
+    println!("  Vendor: {}", egl_ctx.query_string(egl::VENDOR));
+    println!("  Version: {}", egl_ctx.query_string(egl::VERSION));
+    println!("  Configs: {}", egl_ctx.configs.len());
+
+    if egl_ctx.make_current_surfaceless().is_ok() && egl_ctx.init_gl().is_ok() {
+        println!("  GL renderer: {}", egl_ctx.gl_string(gl::RENDERER));
+        println!("  GL version: {}", egl_ctx.gl_string(gl::VERSION));
+    }
+
+    println!("  Extensions:");
+    for ext in egl_ctx.query_string(egl::EXTENSIONS).split_whitespace() {
+        println!("    {}", ext);
+    }
+
+    let formats = egl_ctx.dmabuf_formats();
+    println!("  Importable dmabuf formats ({}):", formats.len());
+    for code in formats {
+        println!("    {}", format::fourcc_name(code));
+    }
+
+    Ok(())
+}
+
+/// Capture a single frame of an output and print exactly what the compositor exported
+pub fn probe_source_formats(
+    conn: &mut WaylandConnection,
+    output_name: &str,
+    include_cursor: bool,
+    backend: CaptureBackend,
+) -> Result<()> {
+    let output = conn
+        .state
+        .output_manager
+        .get_by_name(output_name)
+        .ok_or_else(|| anyhow!("Output '{}' not found", output_name))?
+        .wl_output
+        .clone();
+
+    let capture = Capture::new(&conn.state, backend)?;
+    let qh = conn.queue_handle();
+    capture.request_frame(&conn.state, &output, &qh, include_cursor)?;
+    while !capture.is_done() {
+        conn.roundtrip()?;
+    }
+
+    let frame = capture
+        .take_frame()?
+        .ok_or_else(|| anyhow!("Compositor cancelled the capture of {}", output_name))?;
+
+    println!("{}:", output_name);
+    println!("  Backend: {}", capture.name());
+    println!("  Format: {}", format::fourcc_name(frame.format));
+    println!("  Size: {}x{}", frame.width, frame.height);
+    println!("  Planes: {}", frame.planes.len());
+    for (i, plane) in frame.planes.iter().enumerate() {
+        println!(
+            "    [{}] offset {} stride {} modifier {}",
+            i,
+            plane.offset,
+            plane.stride,
+            format::modifier_name(plane.modifier)
+        );
+    }
+
+    Ok(())
+}
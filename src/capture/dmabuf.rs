@@ -1,5 +1,6 @@
-use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use wayland_client::{protocol::wl_output, Connection, Dispatch, QueueHandle};
 use wayland_protocols_wlr::export_dmabuf::v1::client::{
     zwlr_export_dmabuf_frame_v1::{self, ZwlrExportDmabufFrameV1},
@@ -13,7 +14,6 @@ pub struct DmabufPlane {
     pub fd: RawFd,
     pub offset: u32,
     pub stride: u32,
-    #[allow(dead_code)]
     pub modifier: u64,
 }
 
@@ -25,6 +25,10 @@ pub struct CapturedFrame {
     pub planes: Vec<DmabufPlane>,
     #[allow(dead_code)]
     pub fds: Vec<OwnedFd>, // Keep fds alive
+    /// When the frame was requested from the compositor
+    pub requested_at: Instant,
+    /// When the compositor's Ready event arrived
+    pub ready_at: Instant,
 }
 
 /// Newtype wrapper for frame capture state to satisfy orphan rules
@@ -35,11 +39,16 @@ pub struct FrameCaptureState {
     pub width: u32,
     pub height: u32,
     pub format: u32,
+    pub modifier: u64,
     pub num_objects: u32,
     pub planes: Vec<DmabufPlane>,
     pub fds: Vec<OwnedFd>,
     pub done: bool,
     pub cancelled: bool,
+    /// When the current frame was requested
+    requested_at: Instant,
+    /// Plane count of the last complete frame, to size the next frame's vectors up front
+    plane_capacity: usize,
 }
 
 impl FrameCaptureState {
@@ -49,24 +58,136 @@ impl FrameCaptureState {
             width: 0,
             height: 0,
             format: 0,
+            modifier: 0,
             num_objects: 0,
             planes: Vec::new(),
             fds: Vec::new(),
             done: false,
             cancelled: false,
+            requested_at: Instant::now(),
+            plane_capacity: 0,
         }
     }
 
+    /// Prepare for the next frame. The previous frame took its vectors with it,
+    /// so allocate new ones once at the size that frame needed.
     pub fn reset(&mut self) {
         self.frame = None;
         self.width = 0;
         self.height = 0;
         self.format = 0;
+        self.modifier = 0;
         self.num_objects = 0;
         self.planes.clear();
         self.fds.clear();
+        self.planes.reserve(self.plane_capacity);
+        self.fds.reserve(self.plane_capacity);
         self.done = false;
         self.cancelled = false;
+        self.requested_at = Instant::now();
+    }
+
+    /// Frame event: describes the buffer; a repeated Frame starts over
+    pub fn on_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: u32,
+        modifier: u64,
+        num_objects: u32,
+    ) {
+        if self.is_finished() {
+            return;
+        }
+        self.planes.clear();
+        self.fds.clear();
+        self.planes.reserve(num_objects as usize);
+        self.fds.reserve(num_objects as usize);
+        self.width = width;
+        self.height = height;
+        self.format = format;
+        self.modifier = modifier;
+        self.num_objects = num_objects;
+    }
+
+    /// Object event: one plane's fd; objects may arrive in any order.
+    ///
+    /// Objects outside the announced count (or before any Frame event) are
+    /// dropped, which closes their fd.
+    pub fn on_object(&mut self, fd: OwnedFd, plane_index: u32, offset: u32, stride: u32) {
+        if self.is_finished() || plane_index >= self.num_objects {
+            return;
+        }
+
+        // Ensure we have enough space
+        let index = plane_index as usize;
+        if self.planes.len() <= index {
+            let missing = DmabufPlane {
+                fd: -1,
+                offset: 0,
+                stride: 0,
+                modifier: 0,
+            };
+            self.planes.resize(index + 1, missing);
+        }
+        // A repeated object replaces the earlier one, whose fd is closed
+        let replaced = self.planes[index].fd;
+        if replaced >= 0 {
+            self.fds.retain(|fd| fd.as_raw_fd() != replaced);
+        }
+
+        self.planes[index] = DmabufPlane {
+            fd: fd.as_raw_fd(),
+            offset,
+            stride,
+            modifier: self.modifier,
+        };
+        self.fds.push(fd);
+    }
+
+    /// Ready event: publish the frame if every announced plane arrived.
+    ///
+    /// A frame with missing planes is never marked done; it is treated as
+    /// cancelled so the caller simply requests the next one.
+    pub fn on_ready(&mut self) {
+        if self.is_finished() {
+            return;
+        }
+        let complete = self.num_objects > 0
+            && self.planes.len() == self.num_objects as usize
+            && self.planes.iter().all(|p| p.fd >= 0);
+        if !complete {
+            self.on_cancel();
+            return;
+        }
+
+        self.plane_capacity = self.planes.len();
+        self.frame = Some(CapturedFrame {
+            width: self.width,
+            height: self.height,
+            format: self.format,
+            planes: std::mem::take(&mut self.planes),
+            fds: std::mem::take(&mut self.fds),
+            requested_at: self.requested_at,
+            ready_at: Instant::now(),
+        });
+        self.done = true;
+    }
+
+    /// Cancel event: discard everything received so far
+    pub fn on_cancel(&mut self) {
+        if self.is_finished() {
+            return;
+        }
+        self.frame = None;
+        self.planes.clear();
+        self.fds.clear();
+        self.cancelled = true;
+    }
+
+    /// Done and cancelled are terminal; later events are ignored
+    pub fn is_finished(&self) -> bool {
+        self.done || self.cancelled
     }
 }
 
@@ -127,13 +248,13 @@ impl Dispatch<ZwlrExportDmabufFrameV1, FrameCaptureData> for AppState {
                 width,
                 height,
                 format,
+                mod_high,
+                mod_low,
                 num_objects,
                 ..
             } => {
-                capture.width = width;
-                capture.height = height;
-                capture.format = format;
-                capture.num_objects = num_objects;
+                let modifier = ((mod_high as u64) << 32) | mod_low as u64;
+                capture.on_frame(width, height, format, modifier, num_objects);
             }
             zwlr_export_dmabuf_frame_v1::Event::Object {
                 fd,
@@ -142,43 +263,235 @@ impl Dispatch<ZwlrExportDmabufFrameV1, FrameCaptureData> for AppState {
                 plane_index,
                 ..
             } => {
-                let owned_fd = unsafe { OwnedFd::from_raw_fd(fd.as_raw_fd()) };
-                std::mem::forget(fd); // Don't close the original
-
-                // Ensure we have enough space
-                while capture.planes.len() <= plane_index as usize {
-                    capture.planes.push(DmabufPlane {
-                        fd: -1,
-                        offset: 0,
-                        stride: 0,
-                        modifier: 0,
-                    });
-                }
-
-                capture.planes[plane_index as usize] = DmabufPlane {
-                    fd: owned_fd.as_raw_fd(),
-                    offset,
-                    stride,
-                    modifier: 0,
-                };
-                capture.fds.push(owned_fd);
+                capture.on_object(fd, plane_index, offset, stride);
             }
             zwlr_export_dmabuf_frame_v1::Event::Ready { .. } => {
-                capture.frame = Some(CapturedFrame {
-                    width: capture.width,
-                    height: capture.height,
-                    format: capture.format,
-                    planes: capture.planes.clone(),
-                    fds: std::mem::take(&mut capture.fds),
-                });
-                capture.done = true;
+                capture.on_ready();
                 proxy.destroy();
             }
             zwlr_export_dmabuf_frame_v1::Event::Cancel { .. } => {
-                capture.cancelled = true;
+                capture.on_cancel();
                 proxy.destroy();
             }
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Capture, PendingFrame};
+    use std::os::unix::net::UnixStream;
+    use std::sync::MutexGuard;
+
+    /// Held by every test opening descriptors, so counting them isn't thrown off
+    static FDS: Mutex<()> = Mutex::new(());
+
+    fn lock_fds() -> MutexGuard<'static, ()> {
+        FDS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Descriptors open in this process
+    fn open_fds() -> usize {
+        std::fs::read_dir("/proc/self/fd").unwrap().count()
+    }
+
+    /// A real descriptor to hand over as a plane; its pipe's other end is closed
+    fn plane_fd() -> OwnedFd {
+        let (reader, _writer) = std::io::pipe().unwrap();
+        reader.into()
+    }
+
+    /// State after a Frame event announcing `num_objects` planes
+    fn frame_with(num_objects: u32) -> FrameCaptureState {
+        let mut state = FrameCaptureState::new();
+        state.on_frame(1920, 1080, 0x34325258, 0, num_objects);
+        state
+    }
+
+    #[test]
+    fn objects_may_arrive_out_of_order() {
+        let _fds = lock_fds();
+        let mut state = frame_with(2);
+        state.on_object(plane_fd(), 1, 100, 20);
+        state.on_object(plane_fd(), 0, 0, 10);
+        state.on_ready();
+
+        assert!(state.done);
+        let frame = state.frame.take().unwrap();
+        let layout: Vec<_> = frame.planes.iter().map(|p| (p.offset, p.stride)).collect();
+        assert_eq!(layout, [(0, 10), (100, 20)]);
+        assert_eq!(frame.fds.len(), 2);
+    }
+
+    #[test]
+    fn missing_object_is_not_done() {
+        let _fds = lock_fds();
+        let mut state = frame_with(2);
+        state.on_object(plane_fd(), 0, 0, 10);
+        state.on_ready();
+
+        assert!(!state.done);
+        assert!(state.cancelled);
+        assert!(state.frame.is_none());
+        assert!(state.fds.is_empty());
+    }
+
+    #[test]
+    fn repeated_frame_discards_earlier_planes() {
+        let _fds = lock_fds();
+        let mut state = frame_with(2);
+        state.on_object(plane_fd(), 0, 0, 10);
+        state.on_object(plane_fd(), 1, 100, 20);
+        state.on_frame(1280, 720, 0x34325258, 0, 1);
+        state.on_object(plane_fd(), 0, 7, 30);
+        state.on_ready();
+
+        assert!(state.done);
+        let frame = state.frame.take().unwrap();
+        assert_eq!((frame.width, frame.height), (1280, 720));
+        assert_eq!(frame.planes.len(), 1);
+        assert_eq!((frame.planes[0].offset, frame.planes[0].stride), (7, 30));
+        assert_eq!(frame.fds.len(), 1);
+    }
+
+    #[test]
+    fn repeated_plane_index_keeps_the_last_object() {
+        let _fds = lock_fds();
+        let mut state = frame_with(1);
+        state.on_object(plane_fd(), 0, 0, 10);
+        let last = plane_fd();
+        let last_raw = last.as_raw_fd();
+        state.on_object(last, 0, 5, 20);
+        state.on_ready();
+
+        let frame = state.frame.take().unwrap();
+        assert_eq!(frame.planes.len(), 1);
+        assert_eq!(frame.planes[0].fd, last_raw);
+        assert_eq!(frame.planes[0].offset, 5);
+        assert_eq!(frame.fds.len(), 1);
+        assert_eq!(frame.fds[0].as_raw_fd(), last_raw);
+    }
+
+    #[test]
+    fn object_outside_announced_count_is_dropped() {
+        let _fds = lock_fds();
+        let mut state = frame_with(1);
+        state.on_object(plane_fd(), 1, 0, 10);
+        assert!(state.planes.is_empty());
+        assert!(state.fds.is_empty());
+    }
+
+    #[test]
+    fn cancel_before_ready_discards_the_frame() {
+        let _fds = lock_fds();
+        let mut state = frame_with(1);
+        state.on_object(plane_fd(), 0, 0, 10);
+        state.on_cancel();
+
+        assert!(state.cancelled);
+        assert!(state.planes.is_empty());
+        assert!(state.fds.is_empty());
+
+        state.on_ready();
+        assert!(!state.done);
+        assert!(state.frame.is_none());
+    }
+
+    #[test]
+    fn events_after_done_are_ignored() {
+        let _fds = lock_fds();
+        let mut state = frame_with(1);
+        state.on_object(plane_fd(), 0, 0, 10);
+        state.on_ready();
+        assert!(state.done);
+
+        state.on_frame(640, 480, 0, 0, 1);
+        state.on_object(plane_fd(), 0, 9, 9);
+        state.on_cancel();
+
+        assert!(state.done);
+        assert!(!state.cancelled);
+        let frame = state.frame.take().unwrap();
+        assert_eq!((frame.width, frame.height), (1920, 1080));
+        assert_eq!(frame.planes[0].offset, 0);
+    }
+
+    #[test]
+    fn events_after_cancel_are_ignored() {
+        let _fds = lock_fds();
+        let mut state = frame_with(1);
+        state.on_cancel();
+
+        state.on_frame(640, 480, 0, 0, 1);
+        state.on_object(plane_fd(), 0, 0, 10);
+        state.on_ready();
+
+        assert!(state.cancelled);
+        assert!(!state.done);
+        assert!(state.fds.is_empty());
+        assert!(state.frame.is_none());
+    }
+
+    #[test]
+    fn reset_starts_a_new_frame() {
+        let _fds = lock_fds();
+        let mut state = frame_with(1);
+        state.on_cancel();
+        state.reset();
+        state.on_frame(1920, 1080, 0x34325258, 0, 1);
+        state.on_object(plane_fd(), 0, 0, 10);
+        state.on_ready();
+        assert!(state.done);
+        assert!(state.frame.is_some());
+    }
+
+    #[test]
+    fn capture_cycles_close_every_plane() {
+        let _fds = lock_fds();
+        // Requests only pile up on a connection nobody serves
+        let (socket, _server) = UnixStream::pair().unwrap();
+        let conn = Connection::from_socket(socket).unwrap();
+        let queue = conn.new_event_queue::<AppState>();
+        let qh = queue.handle();
+        let registry = conn.display().get_registry(&qh, ());
+        let manager: ZwlrExportDmabufManagerV1 = registry.bind(1, 1, &qh, ());
+        let output: wl_output::WlOutput = registry.bind(2, 4, &qh, 2);
+        let dmabuf = DmabufCapture::new();
+        let state = dmabuf.capture_state.clone();
+        let capture = Capture::Dmabuf(dmabuf);
+
+        let before = open_fds();
+        for _ in 0..100 {
+            let Capture::Dmabuf(dmabuf) = &capture else {
+                unreachable!()
+            };
+            let frame = dmabuf.request_frame(&manager, &output, &qh, false);
+            {
+                let mut state = state.lock().unwrap();
+                // Cancelled halfway, with a plane sent twice
+                state.reset();
+                state.on_frame(1920, 1080, 0x34325258, 0, 2);
+                state.on_object(plane_fd(), 0, 0, 10);
+                state.on_object(plane_fd(), 0, 0, 10);
+                state.on_cancel();
+
+                // Completed and dropped without being drawn
+                state.reset();
+                state.on_frame(1920, 1080, 0x34325258, 0, 2);
+                state.on_object(plane_fd(), 0, 0, 10);
+                state.on_object(plane_fd(), 1, 100, 20);
+                state.on_ready();
+                state.frame.take();
+
+                // Still in flight when abandoned
+                state.reset();
+                state.on_frame(1920, 1080, 0x34325258, 0, 2);
+                state.on_object(plane_fd(), 0, 0, 10);
+            }
+            capture.abandon(PendingFrame::Dmabuf(frame));
+        }
+        assert_eq!(open_fds(), before);
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{bail, Context, Result};
+use nix::libc;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::capture::CapturedFrame;
+use crate::format;
+use crate::render::EglContext;
+
+/// DRM_FORMAT_MOD_LINEAR: the only layout whose bytes make sense when mapped directly
+const MOD_LINEAR: u64 = 0;
+
+// linux/dma-buf.h
+const DMA_BUF_SYNC_READ: u64 = 1;
+const DMA_BUF_SYNC_START: u64 = 0;
+const DMA_BUF_SYNC_END: u64 = 4;
+const DMA_BUF_IOCTL_SYNC: libc::c_ulong = 0x4008_6200; // _IOW('b', 0, u64)
+
+/// Write a frame's raw plane bytes to `path`, after a plain-text header describing them.
+///
+/// Linear buffers are mapped and written as-is. Tiled or compressed layouts
+/// can't be read directly, so they are drawn and read back through GL as
+/// top-down RGBA8888 instead.
+pub fn write_frame(frame: &CapturedFrame, path: &Path, egl_ctx: &mut EglContext) -> Result<()> {
+    let linear = frame.planes.iter().all(|p| p.modifier == MOD_LINEAR);
+
+    let mut header = String::from("sway-mirror frame dump\n");
+    header += &format!("format: {}\n", format::fourcc_name(frame.format));
+    header += &format!("size: {}x{}\n", frame.width, frame.height);
+
+    let mut data = Vec::new();
+    if linear {
+        for (i, plane) in frame.planes.iter().enumerate() {
+            let bytes = read_plane(plane.fd, plane.offset, plane.stride, frame.height)
+                .with_context(|| format!("Failed to map plane {}", i))?;
+            header += &format!(
+                "plane {}: offset {} stride {} modifier {} bytes {}\n",
+                i,
+                plane.offset,
+                plane.stride,
+                format::modifier_name(plane.modifier),
+                bytes.len()
+            );
+            data.extend(bytes);
+        }
+    } else {
+        for (i, plane) in frame.planes.iter().enumerate() {
+            header += &format!(
+                "plane {}: offset {} stride {} modifier {} (not dumped)\n",
+                i,
+                plane.offset,
+                plane.stride,
+                format::modifier_name(plane.modifier)
+            );
+        }
+        data = egl_ctx.read_back(frame)?;
+        header += &format!(
+            "readback: RGBA8888 stride {} bytes {}\n",
+            frame.width * 4,
+            data.len()
+        );
+    }
+    header += "\n";
+
+    let mut file =
+        fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(&data)?;
+    Ok(())
+}
+
+/// Copy `rows` rows of a linear plane out of its dmabuf
+fn read_plane(fd: i32, offset: u32, stride: u32, rows: u32) -> Result<Vec<u8>> {
+    let size = unsafe { libc::lseek(fd, 0, libc::SEEK_END) };
+    if size <= 0 {
+        bail!("Could not determine dmabuf size");
+    }
+    let size = size as usize;
+
+    let start = (offset as usize).min(size);
+    let len = (stride as usize * rows as usize).min(size - start);
+
+    let map = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+    if map == libc::MAP_FAILED {
+        bail!("mmap failed: {}", std::io::Error::last_os_error());
+    }
+
+    // Bracket CPU access so caches are coherent with what the GPU wrote
+    let sync = |flags: u64| unsafe { libc::ioctl(fd, DMA_BUF_IOCTL_SYNC, &flags) };
+    sync(DMA_BUF_SYNC_START | DMA_BUF_SYNC_READ);
+    let bytes = unsafe { std::slice::from_raw_parts((map as *const u8).add(start), len) }.to_vec();
+    sync(DMA_BUF_SYNC_END | DMA_BUF_SYNC_READ);
+
+    unsafe { libc::munmap(map, size) };
+    Ok(bytes)
+}
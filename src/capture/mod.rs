@@ -1,3 +1,147 @@
 pub mod dmabuf;
+pub mod dump;
+pub mod screencopy;
+pub mod snapshot;
 
+use anyhow::{anyhow, bail, Result};
+use wayland_client::{protocol::wl_output, Proxy, QueueHandle};
+use wayland_protocols_wlr::export_dmabuf::v1::client::zwlr_export_dmabuf_frame_v1::ZwlrExportDmabufFrameV1;
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1;
+
+use crate::wayland::{AppState, WaylandState};
 pub use dmabuf::{CapturedFrame, DmabufCapture};
+pub use screencopy::ScreencopyCapture;
+
+/// Which capture protocol to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureBackend {
+    /// export-dmabuf when advertised, wlr-screencopy otherwise
+    Auto,
+    Dmabuf,
+    Screencopy,
+}
+
+/// The protocol frames are captured with
+pub enum Capture {
+    /// export-dmabuf: the compositor hands over its own buffers (zero-copy)
+    Dmabuf(DmabufCapture),
+    /// wlr-screencopy: the compositor copies into dmabufs we allocate
+    Screencopy(ScreencopyCapture),
+}
+
+/// A requested frame the compositor hasn't finished yet
+pub enum PendingFrame {
+    Dmabuf(ZwlrExportDmabufFrameV1),
+    Screencopy(ZwlrScreencopyFrameV1),
+}
+
+impl Capture {
+    /// Set up the requested backend, preferring zero-copy export-dmabuf on auto
+    pub fn new(state: &WaylandState, backend: CaptureBackend) -> Result<Self> {
+        let use_screencopy = match backend {
+            CaptureBackend::Auto => state.dmabuf_manager.is_none(),
+            CaptureBackend::Dmabuf => false,
+            CaptureBackend::Screencopy => true,
+        };
+        if !use_screencopy {
+            if state.dmabuf_manager.is_none() {
+                bail!("zwlr_export_dmabuf_manager_v1 not available");
+            }
+            return Ok(Self::Dmabuf(DmabufCapture::new()));
+        }
+
+        let manager = state.screencopy_manager.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Neither zwlr_export_dmabuf_manager_v1 nor zwlr_screencopy_manager_v1 is available"
+            )
+        })?;
+        // Dmabuf copies are negotiated with the buffer_done handshake from version 3
+        if manager.version() < 3 {
+            bail!(
+                "zwlr_screencopy_manager_v1 version {} is too old, version 3 is needed",
+                manager.version()
+            );
+        }
+        if state.linux_dmabuf.is_none() {
+            bail!("zwp_linux_dmabuf_v1 not available, it is needed for screencopy");
+        }
+        Ok(Self::Screencopy(ScreencopyCapture::new()?))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dmabuf(_) => "export-dmabuf",
+            Self::Screencopy(_) => "wlr-screencopy",
+        }
+    }
+
+    pub fn request_frame(
+        &self,
+        state: &WaylandState,
+        output: &wl_output::WlOutput,
+        qh: &QueueHandle<AppState>,
+        include_cursor: bool,
+    ) -> Result<PendingFrame> {
+        Ok(match self {
+            Self::Dmabuf(capture) => {
+                let manager = state
+                    .dmabuf_manager
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("zwlr_export_dmabuf_manager_v1 not available"))?;
+                PendingFrame::Dmabuf(capture.request_frame(manager, output, qh, include_cursor))
+            }
+            Self::Screencopy(capture) => {
+                let manager = state
+                    .screencopy_manager
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("zwlr_screencopy_manager_v1 not available"))?;
+                PendingFrame::Screencopy(capture.request_frame(manager, output, qh, include_cursor))
+            }
+        })
+    }
+
+    /// Take the finished frame, if any, and request the next one right away, so
+    /// the compositor can produce it while this one is rendered
+    pub fn request_next_frame(
+        &self,
+        state: &WaylandState,
+        output: &wl_output::WlOutput,
+        qh: &QueueHandle<AppState>,
+        include_cursor: bool,
+    ) -> Result<(Option<CapturedFrame>, PendingFrame)> {
+        let frame = self.take_frame()?;
+        let pending = self.request_frame(state, output, qh, include_cursor)?;
+        Ok((frame, pending))
+    }
+
+    /// Give up on a frame: destroy its request and drop (closing) any fds received for it
+    pub fn abandon(&self, pending: PendingFrame) {
+        match (self, pending) {
+            (Self::Dmabuf(capture), PendingFrame::Dmabuf(frame)) => {
+                frame.destroy();
+                capture.capture_state.lock().unwrap().reset();
+            }
+            (Self::Screencopy(capture), PendingFrame::Screencopy(frame)) => {
+                frame.destroy();
+                capture.capture_state.lock().unwrap().reset();
+            }
+            (_, PendingFrame::Dmabuf(frame)) => frame.destroy(),
+            (_, PendingFrame::Screencopy(frame)) => frame.destroy(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self {
+            Self::Dmabuf(capture) => capture.is_done(),
+            Self::Screencopy(capture) => capture.is_done(),
+        }
+    }
+
+    /// The captured frame, if it wasn't cancelled; errors are fatal to the backend
+    pub fn take_frame(&self) -> Result<Option<CapturedFrame>> {
+        match self {
+            Self::Dmabuf(capture) => Ok(capture.take_frame()),
+            Self::Screencopy(capture) => capture.take_frame(),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::{AsFd, AsRawFd, OwnedFd};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use wayland_client::{
+    protocol::{wl_buffer::WlBuffer, wl_output},
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols::wp::linux_dmabuf::zv1::client::{
+    zwp_linux_buffer_params_v1, zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use super::dmabuf::{CapturedFrame, DmabufPlane};
+use crate::wayland::AppState;
+
+/// Buffers copied into in turn: one being rendered, one queued for the render
+/// thread, and one the compositor is writing, so a frame is never overwritten
+/// while it is still being drawn
+const POOL_SIZE: usize = 3;
+
+/// A dmabuf we allocated and shared with the compositor as a copy destination
+struct PoolBuffer {
+    bo: gbm::BufferObject<()>,
+    wl_buffer: WlBuffer,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Drop for PoolBuffer {
+    fn drop(&mut self) {
+        self.wl_buffer.destroy();
+    }
+}
+
+/// Newtype wrapper for frame capture state to satisfy orphan rules
+pub struct ScreencopyFrameData(pub Arc<Mutex<ScreencopyState>>);
+
+pub struct ScreencopyState {
+    /// Dmabuf format and size the compositor offered for the current frame
+    offer: Option<(u32, u32, u32)>,
+    /// Pool entry the current frame is being copied into
+    current: Option<usize>,
+    next: usize,
+    frame: Option<CapturedFrame>,
+    done: bool,
+    cancelled: bool,
+    /// When the current frame was requested
+    requested_at: Instant,
+    /// Set when the compositor can't copy into anything we can allocate
+    error: Option<String>,
+    // Declared before the device: buffer objects must be freed first
+    buffers: Vec<PoolBuffer>,
+    gbm: gbm::Device<File>,
+}
+
+impl ScreencopyState {
+    pub fn reset(&mut self) {
+        self.offer = None;
+        self.current = None;
+        self.frame = None;
+        self.done = false;
+        self.cancelled = false;
+        self.requested_at = Instant::now();
+    }
+
+    /// Next pool buffer to copy into, (re)allocating when the offer changed
+    fn buffer(
+        &mut self,
+        linux_dmabuf: &ZwpLinuxDmabufV1,
+        qh: &QueueHandle<AppState>,
+        format: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<WlBuffer> {
+        if self
+            .buffers
+            .first()
+            .is_some_and(|b| (b.format, b.width, b.height) != (format, width, height))
+        {
+            self.buffers.clear();
+            self.next = 0;
+        }
+
+        let index = self.next % POOL_SIZE;
+        self.next = (index + 1) % POOL_SIZE;
+        if index == self.buffers.len() {
+            let buffer = self.allocate(linux_dmabuf, qh, format, width, height)?;
+            self.buffers.push(buffer);
+        }
+        self.current = Some(index);
+        Ok(self.buffers[index].wl_buffer.clone())
+    }
+
+    fn allocate(
+        &self,
+        linux_dmabuf: &ZwpLinuxDmabufV1,
+        qh: &QueueHandle<AppState>,
+        format: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<PoolBuffer> {
+        let fourcc = gbm::Format::try_from(format)
+            .map_err(|_| anyhow!("Unknown screencopy format {:#010x}", format))?;
+        let bo = self
+            .gbm
+            .create_buffer_object::<()>(width, height, fourcc, gbm::BufferObjectFlags::RENDERING)
+            .with_context(|| {
+                format!(
+                    "Failed to allocate {}x{} {:?} buffer",
+                    width, height, fourcc
+                )
+            })?;
+
+        let modifier = u64::from(bo.modifier()?);
+        let params = linux_dmabuf.create_params(qh, ());
+        for plane in 0..bo.plane_count()? as i32 {
+            let fd = bo.fd_for_plane(plane)?;
+            params.add(
+                fd.as_fd(),
+                plane as u32,
+                bo.offset(plane)?,
+                bo.stride_for_plane(plane)?,
+                (modifier >> 32) as u32,
+                modifier as u32,
+            );
+        }
+        let wl_buffer = params.create_immed(
+            width as i32,
+            height as i32,
+            format,
+            zwp_linux_buffer_params_v1::Flags::empty(),
+            qh,
+            (),
+        );
+        params.destroy();
+
+        Ok(PoolBuffer {
+            bo,
+            wl_buffer,
+            format,
+            width,
+            height,
+        })
+    }
+
+    /// Ready event: hand out the buffer the compositor just filled
+    fn on_ready(&mut self) -> Result<()> {
+        let index = self
+            .current
+            .ok_or_else(|| anyhow!("screencopy frame ready before a buffer was attached"))?;
+        let buffer = &self.buffers[index];
+        let modifier = u64::from(buffer.bo.modifier()?);
+
+        let mut planes = Vec::new();
+        let mut fds = Vec::new();
+        for plane in 0..buffer.bo.plane_count()? as i32 {
+            let fd: OwnedFd = buffer.bo.fd_for_plane(plane)?;
+            planes.push(DmabufPlane {
+                fd: fd.as_raw_fd(),
+                offset: buffer.bo.offset(plane)?,
+                stride: buffer.bo.stride_for_plane(plane)?,
+                modifier,
+            });
+            fds.push(fd);
+        }
+
+        self.frame = Some(CapturedFrame {
+            width: buffer.width,
+            height: buffer.height,
+            format: buffer.format,
+            planes,
+            fds,
+            requested_at: self.requested_at,
+            ready_at: Instant::now(),
+        });
+        self.done = true;
+        Ok(())
+    }
+
+    fn fail(&mut self, error: String) {
+        self.error = Some(error);
+        self.cancelled = true;
+    }
+}
+
+/// Captures by asking the compositor to copy the output into dmabufs we allocate
+pub struct ScreencopyCapture {
+    pub capture_state: Arc<Mutex<ScreencopyState>>,
+}
+
+impl ScreencopyCapture {
+    pub fn new() -> Result<Self> {
+        let node = render_node()?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&node)
+            .with_context(|| format!("Failed to open {}", node.display()))?;
+        let gbm = gbm::Device::new(file)
+            .with_context(|| format!("Failed to create GBM device on {}", node.display()))?;
+
+        Ok(Self {
+            capture_state: Arc::new(Mutex::new(ScreencopyState {
+                offer: None,
+                current: None,
+                next: 0,
+                frame: None,
+                done: false,
+                cancelled: false,
+                requested_at: Instant::now(),
+                error: None,
+                buffers: Vec::new(),
+                gbm,
+            })),
+        })
+    }
+
+    pub fn request_frame(
+        &self,
+        manager: &ZwlrScreencopyManagerV1,
+        output: &wl_output::WlOutput,
+        qh: &QueueHandle<AppState>,
+        include_cursor: bool,
+    ) -> ZwlrScreencopyFrameV1 {
+        self.capture_state.lock().unwrap().reset();
+
+        manager.capture_output(
+            if include_cursor { 1 } else { 0 },
+            output,
+            qh,
+            ScreencopyFrameData(self.capture_state.clone()),
+        )
+    }
+
+    pub fn is_done(&self) -> bool {
+        let state = self.capture_state.lock().unwrap();
+        state.done || state.cancelled
+    }
+
+    pub fn take_frame(&self) -> Result<Option<CapturedFrame>> {
+        let mut state = self.capture_state.lock().unwrap();
+        if let Some(error) = state.error.take() {
+            bail!(error);
+        }
+        Ok(state.frame.take())
+    }
+}
+
+/// The first DRM render node, which is where the buffers are allocated
+fn render_node() -> Result<PathBuf> {
+    let mut nodes: Vec<PathBuf> = fs::read_dir("/dev/dri")
+        .context("Failed to list /dev/dri")?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("renderD"))
+        })
+        .collect();
+    nodes.sort();
+    nodes
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No DRM render node found in /dev/dri"))
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameData> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        data: &ScreencopyFrameData,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let mut capture = data.0.lock().unwrap();
+        if capture.done || capture.cancelled {
+            return;
+        }
+
+        match event {
+            zwlr_screencopy_frame_v1::Event::LinuxDmabuf {
+                format,
+                width,
+                height,
+            } => {
+                capture.offer = Some((format, width, height));
+            }
+            // Every buffer type has been announced; pick ours and start the copy
+            zwlr_screencopy_frame_v1::Event::BufferDone => {
+                let Some((format, width, height)) = capture.offer else {
+                    capture.fail(
+                        "Compositor only offers shm buffers for screencopy, which aren't supported"
+                            .to_string(),
+                    );
+                    proxy.destroy();
+                    return;
+                };
+                let Some(ref linux_dmabuf) = state.linux_dmabuf else {
+                    capture.fail("zwp_linux_dmabuf_v1 not available".to_string());
+                    proxy.destroy();
+                    return;
+                };
+                match capture.buffer(linux_dmabuf, qh, format, width, height) {
+                    // Wait for damage, like export-dmabuf waits for the next commit, so
+                    // a static source produces no frames and nothing is redrawn
+                    Ok(buffer) if proxy.version() >= 2 => proxy.copy_with_damage(&buffer),
+                    Ok(buffer) => proxy.copy(&buffer),
+                    Err(e) => {
+                        capture.fail(format!("{:#}", e));
+                        proxy.destroy();
+                    }
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                if let Err(e) = capture.on_ready() {
+                    capture.fail(format!("{:#}", e));
+                }
+                proxy.destroy();
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                capture.cancelled = true;
+                proxy.destroy();
+            }
+            // Every frame is drawn in full, so the damaged regions aren't needed
+            // beyond copy_with_damage holding the copy back until there is some
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use nix::libc;
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Write top-down RGBA8888 pixels as a PNG file
+pub fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    let file =
+        fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// A file name in `dir` stamped with the local time, e.g. `sway-mirror-20260101-120000.123.png`,
+/// or `sway-mirror-20260101-120000.123-HDMI-A-1.png` naming a `target`
+pub fn timestamped_path(dir: &Path, target: Option<&str>) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    dir.join(format!(
+        "sway-mirror-{:04}{:02}{:02}-{:02}{:02}{:02}.{:03}{}.png",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        now.subsec_millis(),
+        target.map(|t| format!("-{}", t)).unwrap_or_default()
+    ))
+}
@@ -0,0 +1,36 @@
+use std::time::{Duration, Instant};
+
+/// Steady presentation cadence, so frames go out evenly spaced however
+/// irregularly they are captured
+pub struct PresentClock {
+    interval: Duration,
+    next: Option<Instant>,
+}
+
+impl PresentClock {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Whether the clock ticked since the last call; the first call always ticks.
+    ///
+    /// Ticks that passed unchecked are taken as one, so later ticks stay on the
+    /// same grid.
+    pub fn tick(&mut self) -> bool {
+        let now = Instant::now();
+        let next = *self.next.get_or_insert(now);
+        if now < next {
+            return false;
+        }
+        let missed = (now - next).as_nanos() / self.interval.as_nanos().max(1) + 1;
+        self.next = Some(next + self.interval * missed as u32);
+        true
+    }
+}
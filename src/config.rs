@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgAction, Parser};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Settings for one target output, from the `[per-output.NAME]` tables
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Scale mode for this target, overriding --scale
+    pub scale: Option<String>,
+}
+
+/// The config file: option defaults keyed by their long name, plus per-output tables
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    #[serde(rename = "per-output", default)]
+    per_output: BTreeMap<String, OutputConfig>,
+    #[serde(flatten)]
+    options: toml::Table,
+}
+
+/// $XDG_CONFIG_HOME/sway-mirror/config.toml, or the same under ~/.config
+fn default_config_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("sway-mirror").join("config.toml"))
+}
+
+/// Parse the command line, taking options it doesn't give from the config file.
+///
+/// The file given with `--config` must exist; the default one is optional.
+/// Returns the parsed options and the per-output settings.
+pub fn parse_with_config<C: Parser>() -> Result<(C, BTreeMap<String, OutputConfig>)> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let matches = C::command().get_matches_from(&args);
+
+    let path = match matches.get_one::<PathBuf>("config") {
+        Some(path) => path.clone(),
+        None => match default_config_path().filter(|path| path.exists()) {
+            Some(path) => path,
+            None => return Ok((C::parse_from(args), BTreeMap::new())),
+        },
+    };
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let config: ConfigFile = toml::from_str(&text)
+        .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+
+    // Options from the file go before the command line's own, as if typed there
+    let mut merged = vec![args[0].clone()];
+    let command = C::command();
+    for (key, value) in &config.options {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key) || (arg.is_positional() && arg.get_id() == key))
+            .filter(|arg| arg.get_id() != "config")
+        else {
+            bail!("Unknown option {} in {}", key, path.display());
+        };
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(items) => items.iter().map(option_value).collect(),
+            value => vec![option_value(value)],
+        };
+        for value in values {
+            let value = value.with_context(|| format!("Option {} in {}", key, path.display()))?;
+            if arg.is_positional() {
+                merged.push(value.into());
+                continue;
+            }
+            match arg.get_action() {
+                ArgAction::SetTrue | ArgAction::SetFalse => match value.as_str() {
+                    "true" => merged.push(format!("--{}", key).into()),
+                    "false" => {}
+                    _ => bail!("Option {} in {} must be true or false", key, path.display()),
+                },
+                // One token, so values starting with '-' aren't taken for options
+                _ => merged.push(format!("--{}={}", key, value).into()),
+            }
+        }
+    }
+    merged.extend(args.into_iter().skip(1));
+
+    Ok((C::parse_from(merged), config.per_output))
+}
+
+/// An option value as it would be typed on the command line
+fn option_value(value: &toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(n) => n.to_string(),
+        toml::Value::Float(x) => x.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        _ => bail!("must be a string, number, boolean, or an array of those"),
+    })
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{RotateArg, ScaleModeArg};
+
+/// How long a client gets to send its commands before it is cut off
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A setting changed through the control socket
+#[derive(Debug, Clone, Copy)]
+pub enum ControlCommand {
+    Scale(ScaleModeArg),
+    Cursor(bool),
+    Rotate(RotateArg),
+    Pause,
+    Resume,
+}
+
+impl ControlCommand {
+    /// Parse one command line, e.g. `scale fill`, `cursor off` or `rotate 90`
+    pub fn parse(line: &str) -> Result<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        Ok(match words.as_slice() {
+            ["scale", mode] => Self::Scale(ScaleModeArg::from_str(mode, true).map_err(|e| anyhow!(e))?),
+            ["cursor", "on"] => Self::Cursor(true),
+            ["cursor", "off"] => Self::Cursor(false),
+            ["rotate", degrees] => Self::Rotate(RotateArg::from_str(degrees, true).map_err(|e| anyhow!(e))?),
+            ["pause"] => Self::Pause,
+            ["resume"] => Self::Resume,
+            _ => bail!(
+                "Unknown command '{}' (expected scale MODE, cursor on|off, rotate DEGREES, pause or resume)",
+                line.trim()
+            ),
+        })
+    }
+}
+
+fn get_socket_path() -> PathBuf {
+    // Lives next to the PID file
+    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+        return PathBuf::from(format!("{}/sway-mirror.sock", dir));
+    }
+    if let Ok(dir) = std::env::var("XDG_STATE_HOME") {
+        return PathBuf::from(format!("{}/sway-mirror.sock", dir));
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        return PathBuf::from(format!("{}/.local/state/sway-mirror.sock", home));
+    }
+    PathBuf::from("/run/user/1000/sway-mirror.sock")
+}
+
+/// The socket a running instance takes commands on, one per line. Each command
+/// is answered with `ok` or `error: REASON`.
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Listen for commands; a socket left behind by an instance that died is replaced
+    pub fn bind() -> Result<Self> {
+        let path = get_socket_path();
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to listen on {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, path })
+    }
+
+    /// Commands from clients that connected since the last poll; never blocks
+    /// for longer than a client takes to send its commands
+    pub fn poll(&self) -> Vec<ControlCommand> {
+        let mut commands = Vec::new();
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("Warning: Control socket: {}", e);
+                    break;
+                }
+            };
+            if let Err(e) = serve(stream, &mut commands) {
+                eprintln!("Warning: Control socket client: {}", e);
+            }
+        }
+        commands
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Read a client's commands until it stops sending, answering each one
+fn serve(stream: UnixStream, commands: &mut Vec<ControlCommand>) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match ControlCommand::parse(&line) {
+            Ok(command) => {
+                commands.push(command);
+                writeln!(writer, "ok")?;
+            }
+            Err(e) => writeln!(writer, "error: {}", e)?,
+        }
+    }
+    Ok(())
+}
+
+/// Send a command to the running instance (`sway-mirror ctl ...`), failing if it is refused
+pub fn send(command: &str) -> Result<()> {
+    let path = get_socket_path();
+    let mut stream = UnixStream::connect(&path).with_context(|| {
+        format!(
+            "Could not reach a running sway-mirror at {} (is one running?)",
+            path.display()
+        )
+    })?;
+    writeln!(stream, "{}", command)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    match reply.trim().strip_prefix("error: ") {
+        Some(error) => bail!("{}", error),
+        None => Ok(()),
+    }
+}
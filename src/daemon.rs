@@ -0,0 +1,104 @@
+use anyhow::{bail, Context, Result};
+use nix::libc;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Where a daemon's output goes: $XDG_STATE_HOME or ~/.local/state
+pub fn log_file_path() -> PathBuf {
+    if let Ok(dir) = std::env::var("XDG_STATE_HOME") {
+        return PathBuf::from(format!("{}/sway-mirror.log", dir));
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        return PathBuf::from(format!("{}/.local/state/sway-mirror.log", home));
+    }
+    PathBuf::from("/tmp/sway-mirror.log")
+}
+
+/// Lets the daemon tell the process that started it that setup succeeded
+pub struct Readiness(Option<File>);
+
+impl Readiness {
+    /// Report success, letting the starting process exit; only the first call counts
+    pub fn notify(&mut self) {
+        if let Some(mut pipe) = self.0.take() {
+            let _ = pipe.write_all(&[1]);
+        }
+    }
+}
+
+/// Detach into the background: fork, start a new session, and fork again so
+/// the daemon can never reacquire a terminal. Stdin is read from /dev/null,
+/// stdout and stderr are appended to `log`.
+///
+/// Only the daemon returns. The starting process waits until the daemon calls
+/// `Readiness::notify`, or exits without doing so, and exits with a matching
+/// status. Must run before any threads are started or connections are made.
+pub fn daemonize(log: &Path) -> Result<Readiness> {
+    if let Some(dir) = log.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .with_context(|| format!("Failed to open log file {}", log.display()))?;
+    let null = File::open("/dev/null").context("Failed to open /dev/null")?;
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        bail!("Failed to create pipe: {}", io::Error::last_os_error());
+    }
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    match unsafe { libc::fork() } {
+        -1 => bail!("Failed to fork: {}", io::Error::last_os_error()),
+        0 => {}
+        _ => {
+            // Wait for the daemon's verdict; the pipe closes without one if it fails
+            drop(writer);
+            let mut ready = [0u8; 1];
+            if reader.read(&mut ready).unwrap_or(0) == 1 {
+                println!(
+                    "sway-mirror is running in the background, logging to {}",
+                    log.display()
+                );
+                process::exit(0);
+            }
+            eprintln!("sway-mirror failed to start, see {}", log.display());
+            process::exit(1);
+        }
+    }
+    drop(reader);
+
+    // The intermediate child leads a new session, without a controlling terminal
+    if unsafe { libc::setsid() } < 0 {
+        eprintln!(
+            "Failed to start a new session: {}",
+            io::Error::last_os_error()
+        );
+        process::exit(1);
+    }
+    match unsafe { libc::fork() } {
+        -1 => {
+            eprintln!("Failed to fork: {}", io::Error::last_os_error());
+            process::exit(1);
+        }
+        0 => {}
+        _ => process::exit(0),
+    }
+
+    unsafe {
+        if libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) < 0
+            || libc::dup2(log_file.as_raw_fd(), libc::STDOUT_FILENO) < 0
+            || libc::dup2(log_file.as_raw_fd(), libc::STDERR_FILENO) < 0
+        {
+            bail!("Failed to redirect output: {}", io::Error::last_os_error());
+        }
+    }
+    Ok(Readiness(Some(writer)))
+}
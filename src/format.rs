@@ -0,0 +1,80 @@
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use serde::Serializer;
+
+/// DRM format names accepted in addition to raw 4-character codes
+const NAMED_FORMATS: &[(&str, DrmFourcc)] = &[
+    ("XRGB8888", DrmFourcc::Xrgb8888),
+    ("ARGB8888", DrmFourcc::Argb8888),
+    ("XBGR8888", DrmFourcc::Xbgr8888),
+    ("ABGR8888", DrmFourcc::Abgr8888),
+    ("RGBX8888", DrmFourcc::Rgbx8888),
+    ("RGBA8888", DrmFourcc::Rgba8888),
+    ("BGRX8888", DrmFourcc::Bgrx8888),
+    ("BGRA8888", DrmFourcc::Bgra8888),
+    ("XRGB2101010", DrmFourcc::Xrgb2101010),
+    ("ARGB2101010", DrmFourcc::Argb2101010),
+    ("XBGR2101010", DrmFourcc::Xbgr2101010),
+    ("ABGR2101010", DrmFourcc::Abgr2101010),
+    ("RGB565", DrmFourcc::Rgb565),
+    ("BGR888", DrmFourcc::Bgr888),
+    ("RGB888", DrmFourcc::Rgb888),
+    ("NV12", DrmFourcc::Nv12),
+    ("YUYV", DrmFourcc::Yuyv),
+];
+
+/// Parse a DRM fourcc from its name (e.g. XRGB8888) or 4-char ASCII code (e.g. XR24)
+pub fn parse_fourcc(s: &str) -> Result<u32, String> {
+    if let Some((_, fourcc)) = NAMED_FORMATS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+    {
+        return Ok(*fourcc as u32);
+    }
+
+    // Raw codes are case-sensitive ("XR24" and "xr24" are different formats)
+    let bytes = s.as_bytes();
+    if bytes.len() == 4 && bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+
+    Err(format!(
+        "unknown format '{}' (use a name like XRGB8888 or a 4-char code like XR24)",
+        s
+    ))
+}
+
+/// Human-readable form of a DRM fourcc, e.g. "XRGB8888 (XR24)"
+pub fn fourcc_name(code: u32) -> String {
+    let ascii: String = code
+        .to_le_bytes()
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() { b as char } else { '?' })
+        .collect();
+
+    match NAMED_FORMATS
+        .iter()
+        .find(|(_, fourcc)| *fourcc as u32 == code)
+    {
+        Some((name, _)) => format!("{} ({})", name, ascii),
+        None => format!("{} (0x{:08x})", ascii, code),
+    }
+}
+
+/// Serialize an optional fourcc in its human-readable form
+pub fn serialize_fourcc<S: Serializer>(
+    code: &Option<u32>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match code {
+        Some(code) => serializer.serialize_some(&fourcc_name(*code)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Human-readable form of a DRM format modifier, e.g. "Linear (0x0000000000000000)"
+pub fn modifier_name(modifier: u64) -> String {
+    match DrmModifier::from(modifier) {
+        DrmModifier::Unrecognized(_) => format!("0x{:016x}", modifier),
+        known => format!("{:?} (0x{:016x})", known, modifier),
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use crate::sway;
+
+/// How often to ask sway whether the source shows any windows
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the source must stay empty before mirroring pauses
+const IDLE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Pauses mirroring while the source output's visible workspace is empty
+pub struct IdleDetector {
+    source: String,
+    last_poll: Option<Instant>,
+    empty_since: Option<Instant>,
+    paused: bool,
+}
+
+impl IdleDetector {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            last_poll: None,
+            empty_since: None,
+            paused: false,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Poll sway if it is time to; returns the new paused state when it changed
+    pub fn poll(&mut self) -> Option<bool> {
+        if self
+            .last_poll
+            .is_some_and(|at| at.elapsed() < IDLE_POLL_INTERVAL)
+        {
+            return None;
+        }
+        self.last_poll = Some(Instant::now());
+
+        // If sway can't be queried, keep mirroring rather than go dark
+        let has_windows = sway::output_has_windows(&self.source).unwrap_or(true);
+        let paused = if has_windows {
+            self.empty_since = None;
+            false
+        } else {
+            let since = *self.empty_since.get_or_insert_with(Instant::now);
+            since.elapsed() >= IDLE_GRACE_PERIOD
+        };
+
+        if paused == self.paused {
+            return None;
+        }
+        self.paused = paused;
+        Some(paused)
+    }
+}
@@ -1,22 +1,56 @@
+mod capabilities;
 mod capture;
+mod clock;
+mod config;
+mod control;
+mod daemon;
+mod format;
+mod idle;
+mod pattern;
+mod presentation;
 mod render;
+mod renderer;
+mod stats;
+mod status;
+mod stream;
 mod sway;
+mod targets;
 mod wayland;
 
 use anyhow::{bail, Result};
-use clap::{Parser, ValueEnum};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use nix::libc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::ffi::c_void;
 use std::fs;
 use std::io::Write;
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use capture::DmabufCapture;
-use render::{EglContext, MirrorSurface, ScaleMode};
-use sway::WorkspaceState;
-use wayland::outputs::request_xdg_outputs;
+use capture::{Capture, CaptureBackend, CapturedFrame};
+use clock::PresentClock;
+use control::{ControlCommand, ControlSocket};
+use idle::IdleDetector;
+use pattern::OutputPattern;
+use presentation::PresentationLog;
+use render::egl::MAX_RENDER_SCALE;
+use render::{
+    ColorFilter, CropAnchor, EglContext, FilterMode, Lut1d, MirrorSurface, ScaleMode, Transform,
+    ViewportTransition,
+};
+use renderer::{RenderLoop, Renderer, Setting};
+use stats::IntervalStats;
+use status::SessionStatus;
+use stream::RgbaStream;
+use sway::{PowerPoll, SwayEvent, SwayEvents, WorkspaceState};
+use targets::{TargetTemplate, TargetTracker};
+use wayland::gamma::GammaDimmer;
+use wayland::outputs::{request_xdg_outputs, OutputManager};
+use wayland::WaylandConnection;
+use wayland_client::protocol::wl_output;
 
 fn get_pid_file_path() -> String {
     // Use XDG_RUNTIME_DIR for security (per-user, proper permissions)
@@ -32,7 +66,8 @@ fn get_pid_file_path() -> String {
     "/run/user/1000/sway-mirror.pid".to_string()
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum, Default)]
+#[derive(Debug, Clone, Copy, ValueEnum, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum ScaleModeArg {
     /// Preserve aspect ratio, fit within target (letterbox/pillarbox)
     #[default]
@@ -43,6 +78,10 @@ pub enum ScaleModeArg {
     Stretch,
     /// Display at 1:1 pixel ratio, centered (no scaling)
     Center,
+    /// Match the target width, scaling height proportionally (may crop or letterbox)
+    FitWidth,
+    /// Match the target height, scaling width proportionally (may crop or pillarbox)
+    FitHeight,
 }
 
 impl From<ScaleModeArg> for ScaleMode {
@@ -52,15 +91,496 @@ impl From<ScaleModeArg> for ScaleMode {
             ScaleModeArg::Fill => ScaleMode::Fill,
             ScaleModeArg::Stretch => ScaleMode::Stretch,
             ScaleModeArg::Center => ScaleMode::Center,
+            ScaleModeArg::FitWidth => ScaleMode::FitWidth,
+            ScaleModeArg::FitHeight => ScaleMode::FitHeight,
         }
     }
 }
 
-#[derive(Parser)]
+#[derive(Debug, Clone, Copy, ValueEnum, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CropAnchorArg {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl From<CropAnchorArg> for CropAnchor {
+    fn from(arg: CropAnchorArg) -> Self {
+        match arg {
+            CropAnchorArg::Center => CropAnchor::Center,
+            CropAnchorArg::Top => CropAnchor::Top,
+            CropAnchorArg::Bottom => CropAnchor::Bottom,
+            CropAnchorArg::Left => CropAnchor::Left,
+            CropAnchorArg::Right => CropAnchor::Right,
+            CropAnchorArg::TopLeft => CropAnchor::TopLeft,
+            CropAnchorArg::TopRight => CropAnchor::TopRight,
+            CropAnchorArg::BottomLeft => CropAnchor::BottomLeft,
+            CropAnchorArg::BottomRight => CropAnchor::BottomRight,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureBackendArg {
+    /// export-dmabuf when the compositor offers it, wlr-screencopy otherwise
+    #[default]
+    Auto,
+    /// Zero-copy capture with wlr-export-dmabuf
+    Dmabuf,
+    /// Have the compositor copy each frame into our own buffers with wlr-screencopy
+    Screencopy,
+}
+
+impl From<CaptureBackendArg> for CaptureBackend {
+    fn from(arg: CaptureBackendArg) -> Self {
+        match arg {
+            CaptureBackendArg::Auto => CaptureBackend::Auto,
+            CaptureBackendArg::Dmabuf => CaptureBackend::Dmabuf,
+            CaptureBackendArg::Screencopy => CaptureBackend::Screencopy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterModeArg {
+    /// Smooth, but blurs 1:1 and magnified pixels
+    Linear,
+    /// Sharp pixels, but aliases when scaling down
+    Nearest,
+}
+
+impl From<FilterModeArg> for FilterMode {
+    fn from(arg: FilterModeArg) -> Self {
+        match arg {
+            FilterModeArg::Linear => FilterMode::Linear,
+            FilterModeArg::Nearest => FilterMode::Nearest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterArg {
+    #[default]
+    None,
+    /// Luminance only
+    Grayscale,
+    /// Simulate missing red cones
+    Protanopia,
+    /// Simulate missing green cones
+    Deuteranopia,
+    /// Simulate missing blue cones
+    Tritanopia,
+    /// Negative image
+    Invert,
+}
+
+impl From<FilterArg> for ColorFilter {
+    fn from(arg: FilterArg) -> Self {
+        match arg {
+            FilterArg::None => ColorFilter::None,
+            FilterArg::Grayscale => ColorFilter::Grayscale,
+            FilterArg::Protanopia => ColorFilter::Protanopia,
+            FilterArg::Deuteranopia => ColorFilter::Deuteranopia,
+            FilterArg::Tritanopia => ColorFilter::Tritanopia,
+            FilterArg::Invert => ColorFilter::Invert,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Change a running instance's settings: scale MODE, cursor on|off, rotate 0|90|180|270, pause or resume
+    Ctl {
+        /// The command and its argument, e.g. `cursor off`
+        #[arg(required = true, num_args = 1..)]
+        command: Vec<String>,
+    },
+    /// Show whether sway-mirror is running and what it is mirroring (same as --status)
+    Status {
+        /// Print the status as JSON, e.g. {"running":true,"pid":1234,"source":"eDP-1",...}
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+enum ListFormatArg {
+    /// One line per output, for people
+    #[default]
+    Text,
+    /// An array of objects with name, description, width, height, refresh (mHz), x, y and scale
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlipArg {
+    #[default]
+    None,
+    /// Mirror left to right
+    Horizontal,
+    /// Mirror top to bottom
+    Vertical,
+    /// Both, which is the same as rotating by 180 degrees
+    Both,
+}
+
+impl From<FlipArg> for Transform {
+    fn from(arg: FlipArg) -> Self {
+        match arg {
+            FlipArg::None => Transform::Normal,
+            FlipArg::Horizontal => Transform::Flipped,
+            FlipArg::Vertical => Transform::Flipped180,
+            FlipArg::Both => Transform::Rotate180,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, Serialize)]
+pub enum RotateArg {
+    #[default]
+    #[value(name = "0")]
+    #[serde(rename = "0")]
+    Rotate0,
+    #[value(name = "90")]
+    #[serde(rename = "90")]
+    Rotate90,
+    #[value(name = "180")]
+    #[serde(rename = "180")]
+    Rotate180,
+    #[value(name = "270")]
+    #[serde(rename = "270")]
+    Rotate270,
+}
+
+impl From<RotateArg> for Transform {
+    fn from(arg: RotateArg) -> Self {
+        match arg {
+            RotateArg::Rotate0 => Transform::Normal,
+            RotateArg::Rotate90 => Transform::Rotate90,
+            RotateArg::Rotate180 => Transform::Rotate180,
+            RotateArg::Rotate270 => Transform::Rotate270,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputRegionArg {
+    /// Pointer input passes through the mirror (click-through)
+    #[default]
+    Empty,
+    /// The mirror receives pointer input over its whole area
+    Full,
+}
+
+/// A color temperature for one target output, or for all targets when no output is named
+#[derive(Debug, Clone, Serialize)]
+pub struct TemperatureArg {
+    output: Option<String>,
+    kelvin: u32,
+}
+
+/// Parse `[OUTPUT=]KELVIN`, e.g. `5000` or `DP-7=5500`
+fn parse_temperature(s: &str) -> Result<TemperatureArg, String> {
+    let (output, kelvin) = match s.rsplit_once('=') {
+        Some((output, kelvin)) => (Some(output.to_string()), kelvin),
+        None => (None, s),
+    };
+    let kelvin: u32 = kelvin
+        .trim_end_matches(['K', 'k'])
+        .parse()
+        .map_err(|_| format!("invalid color temperature '{}'", kelvin))?;
+    if !(1000..=40000).contains(&kelvin) {
+        return Err(format!(
+            "color temperature {}K is outside 1000K..40000K",
+            kelvin
+        ));
+    }
+    Ok(TemperatureArg { output, kelvin })
+}
+
+/// Parse `RRGGBB` or `RRGGBBAA` (optionally prefixed with `#`) into RGBA bytes
+fn parse_background(s: &str) -> Result<[u8; 4], String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "invalid color '{}' (expected RRGGBB or RRGGBBAA)",
+            s
+        ));
+    }
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0);
+    let alpha = if hex.len() == 8 { byte(6) } else { 0xff };
+    Ok([byte(0), byte(2), byte(4), alpha])
+}
+
+/// A fixed source rectangle shown on one target
+#[derive(Debug, Clone, Serialize)]
+pub struct TileArg {
+    output: String,
+    /// x, y, width, height in source buffer pixels
+    region: (u32, u32, u32, u32),
+}
+
+/// Parse `OUTPUT=X,Y,W,H`, e.g. `DP-1=0,0,960,540`
+fn parse_tile(s: &str) -> Result<TileArg, String> {
+    let (output, rect) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected OUTPUT=X,Y,W,H, got '{}'", s))?;
+    let values: Vec<u32> = rect
+        .split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid tile rectangle '{}'", rect))?;
+    let [x, y, w, h] = values[..] else {
+        return Err(format!("tile rectangle needs 4 values, got '{}'", rect));
+    };
+    if w == 0 || h == 0 {
+        return Err(format!("tile rectangle '{}' is empty", rect));
+    }
+    Ok(TileArg {
+        output: output.to_string(),
+        region: (x, y, w, h),
+    })
+}
+
+/// A scale mode for one target
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetScaleArg {
+    output: String,
+    mode: ScaleModeArg,
+}
+
+/// Parse `OUTPUT=MODE`, e.g. `DP-1=stretch`
+fn parse_target_scale(s: &str) -> Result<TargetScaleArg, String> {
+    let (output, mode) = s
+        .split_once('=')
+        .filter(|(output, _)| !output.is_empty())
+        .ok_or_else(|| format!("expected OUTPUT=MODE, got '{}'", s))?;
+    Ok(TargetScaleArg {
+        output: output.to_string(),
+        mode: ScaleModeArg::from_str(mode, true)?,
+    })
+}
+
+/// Parse `X,Y,WxH`, e.g. `100,200,640x360`
+fn parse_region(s: &str) -> Result<(u32, u32, u32, u32), String> {
+    let invalid = || format!("expected X,Y,WxH, got '{}'", s);
+    let (x, rest) = s.split_once(',').ok_or_else(invalid)?;
+    let (y, size) = rest.split_once(',').ok_or_else(invalid)?;
+    let (w, h) = size.split_once('x').ok_or_else(invalid)?;
+    let parse = |v: &str| v.trim().parse::<u32>().map_err(|_| invalid());
+    let (x, y, w, h) = (parse(x)?, parse(y)?, parse(w)?, parse(h)?);
+    if w == 0 || h == 0 {
+        return Err(format!("region '{}' is empty", s));
+    }
+    Ok((x, y, w, h))
+}
+
+/// Print a line for every output that is connected or disconnected, until killed
+fn watch_outputs(conn: &mut WaylandConnection) -> Result<()> {
+    let mut known: HashMap<u32, String> = conn
+        .state
+        .output_manager
+        .outputs
+        .iter()
+        .map(|(global, output)| (*global, output.name.clone()))
+        .collect();
+
+    loop {
+        conn.blocking_dispatch()?;
+        // New outputs only get their name and mode in follow-up events
+        conn.roundtrip()?;
+
+        let outputs = &conn.state.output_manager.outputs;
+        known.retain(|global, name| {
+            let present = outputs.contains_key(global);
+            if !present {
+                println!("- {}", name);
+            }
+            present
+        });
+        for (global, output) in outputs {
+            if !known.contains_key(global) && !output.name.is_empty() {
+                println!(
+                    "+ {} - {} ({}x{})",
+                    output.name, output.description, output.width, output.height
+                );
+                known.insert(*global, output.name.clone());
+            }
+        }
+        std::io::stdout().flush()?;
+    }
+}
+
+/// Capture one frame of the source and import it, failing if either doesn't work
+fn verify_source_capturable(
+    conn: &mut WaylandConnection,
+    capture: &Capture,
+    egl_ctx: &mut EglContext,
+    output: &wl_output::WlOutput,
+    name: &str,
+    cursor: bool,
+    fourcc_override: Option<u32>,
+) -> Result<()> {
+    let qh = conn.queue_handle();
+
+    // A single cancel can just be bad timing (e.g. a mode set), so allow a few
+    for _ in 0..PREFLIGHT_ATTEMPTS {
+        capture.request_frame(&conn.state, output, &qh, cursor)?;
+        while !capture.is_done() {
+            conn.roundtrip()?;
+        }
+        if let Some(mut frame) = capture.take_frame()? {
+            if let Some(code) = fourcc_override {
+                frame.format = code;
+            }
+            return egl_ctx.verify_import(&frame).map_err(|e| {
+                anyhow::anyhow!(
+                    "{} can be captured, but its {} {}x{} frames can't be imported: {}",
+                    name,
+                    format::fourcc_name(frame.format),
+                    frame.width,
+                    frame.height,
+                    e
+                )
+            });
+        }
+    }
+    bail!(
+        "Compositor cancelled {} trial captures of {}",
+        PREFLIGHT_ATTEMPTS,
+        name
+    )
+}
+
+/// Turn the source back on, as it stops producing frames while off, and dim it
+/// through its gamma tables instead where the compositor allows
+fn dim_instead_of_blank(
+    conn: &mut WaylandConnection,
+    name: &str,
+    output: &wl_output::WlOutput,
+) -> Result<Option<GammaDimmer>> {
+    eprintln!(
+        "Warning: {} produces no frames while turned off, turning it back on and dimming it instead",
+        name
+    );
+    if let Err(e) = sway::set_output_power(name, true) {
+        eprintln!("Warning: Could not turn {} back on: {}", name, e);
+    }
+    let dimmer = match GammaDimmer::new(&conn.state, output, &conn.queue_handle()) {
+        Ok(dimmer) => dimmer,
+        Err(e) => {
+            eprintln!("Warning: Could not dim {}: {}", name, e);
+            return Ok(None);
+        }
+    };
+    // The gamma size arrives in reply
+    conn.roundtrip()?;
+    match dimmer.dim(SOURCE_DIM_LEVEL) {
+        Ok(()) => Ok(Some(dimmer)),
+        Err(e) => {
+            eprintln!("Warning: Could not dim {}: {}", name, e);
+            Ok(None)
+        }
+    }
+}
+
+/// Submit the held frame if the present clock ticked (--smooth); returns whether it did.
+/// On a tick with no new frame, the targets keep showing the last one.
+fn present_held_frame(
+    clock: &mut Option<PresentClock>,
+    held: &mut Option<CapturedFrame>,
+    render_loop: &mut RenderLoop,
+) -> Result<bool> {
+    if !clock.as_mut().is_some_and(PresentClock::tick) {
+        return Ok(false);
+    }
+    let Some(frame) = held.take() else {
+        return Ok(false);
+    };
+    render_loop.submit(frame)?;
+    Ok(true)
+}
+
+/// Sleep until `deadline` in short steps, so Ctrl+C still stops us promptly.
+///
+/// Returns whether any time was slept.
+fn sleep_until(deadline: Instant, running: &AtomicBool) -> bool {
+    let mut slept = false;
+    while running.load(Ordering::SeqCst) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        std::thread::sleep(remaining.min(Duration::from_millis(10)));
+        slept = true;
+    }
+    slept
+}
+
+/// Pass each target output's current scale on to its surface
+fn sync_target_scales(outputs: &OutputManager, scales: &[(String, Arc<Mutex<i32>>)]) {
+    for (name, scale) in scales {
+        let Some(output) = outputs.get_by_name(name) else {
+            continue;
+        };
+        let mut scale = scale.lock().unwrap();
+        if *scale != output.scale.max(1) {
+            *scale = output.scale.max(1);
+            println!("{} is at scale {}, resizing mirror", name, *scale);
+        }
+    }
+}
+
+/// A calibration LUT file for one target
+#[derive(Debug, Clone, Serialize)]
+pub struct LutArg {
+    output: String,
+    path: std::path::PathBuf,
+}
+
+/// Parse `OUTPUT=PATH`, e.g. `DP-1=dp1.cube`
+fn parse_lut(s: &str) -> Result<LutArg, String> {
+    let (output, path) = s
+        .split_once('=')
+        .filter(|(output, path)| !output.is_empty() && !path.is_empty())
+        .ok_or_else(|| format!("expected OUTPUT=PATH, got '{}'", s))?;
+    Ok(LutArg {
+        output: output.to_string(),
+        path: path.into(),
+    })
+}
+
+/// Color temperature for a target: its own entry wins over a global one (last given wins)
+fn temperature_for(temperatures: &[TemperatureArg], output: &str) -> u32 {
+    temperatures
+        .iter()
+        .rev()
+        .find(|t| t.output.as_deref() == Some(output))
+        .or_else(|| temperatures.iter().rev().find(|t| t.output.is_none()))
+        .map_or(render::NEUTRAL_TEMPERATURE, |t| t.kelvin)
+}
+
+#[derive(Parser, Serialize)]
+#[serde(rename_all = "kebab-case")]
 #[command(name = "sway-mirror")]
 #[command(about = "Fast zero-copy screen mirroring for Sway")]
 struct Cli {
-    /// Source output to mirror (e.g., eDP-1, DP-7)
+    /// Source output to mirror (e.g., eDP-1, DP-7), or a pattern matching exactly one
+    /// output: re:REGEX or glob:GLOB (e.g. 'glob:DP-*'), or @focused for the output
+    /// focused at startup
     source: Option<String>,
 
     /// Target outputs (if not specified, mirrors to all other outputs)
@@ -69,25 +589,356 @@ struct Cli {
 
     /// List available outputs and exit
     #[arg(short, long)]
+    #[serde(skip)]
     list: bool,
 
+    /// Format of the --list output
+    #[arg(
+        long,
+        value_enum,
+        default_value = "text",
+        requires = "list",
+        conflicts_with = "watch"
+    )]
+    #[serde(skip)]
+    format: ListFormatArg,
+
+    /// With --list, keep running and print a line as outputs are connected (+) or removed (-)
+    #[arg(long, requires = "list")]
+    #[serde(skip)]
+    watch: bool,
+
+    /// Print compositor, protocol and EGL capabilities and exit
+    #[arg(long)]
+    #[serde(skip)]
+    capabilities: bool,
+
+    /// Capture one frame of OUTPUT, print its dmabuf format and layout, and exit
+    #[arg(long, value_name = "OUTPUT")]
+    #[serde(skip)]
+    probe_source_formats: Option<String>,
+
     /// Include cursor in mirror
-    #[arg(long, default_value = "true")]
+    #[arg(long, default_value = "true", num_args = 0..=1, default_missing_value = "true", action = ArgAction::Set)]
     cursor: bool,
 
     /// Scaling mode for the mirrored content
     #[arg(short, long, value_enum, default_value = "fit")]
     scale: ScaleModeArg,
 
+    /// Scaling mode for one target, overriding --scale, e.g. --target-scale DP-1=stretch (repeatable)
+    #[arg(long, value_name = "OUTPUT=MODE", value_parser = parse_target_scale)]
+    target_scale: Vec<TargetScaleArg>,
+
+    /// Which part of the source to keep when fill mode crops it
+    #[arg(long, value_enum, default_value = "center")]
+    crop_anchor: CropAnchorArg,
+
+    /// Rotate the mirrored image clockwise by this many degrees, e.g. for portrait targets
+    #[arg(long, value_enum, default_value = "0")]
+    rotate: RotateArg,
+
+    /// Color of the letterbox bars as RRGGBB or RRGGBBAA; alpha below FF lets what is
+    /// beneath the mirror show through
+    #[arg(long, value_name = "COLOR", default_value = "000000", value_parser = parse_background)]
+    background: [u8; 4],
+
+    /// Flip the mirrored image, e.g. for teleprompters; applied before --rotate
+    #[arg(long, value_enum, default_value = "none")]
+    flip: FlipArg,
+
     /// Move all workspaces to source output while mirroring (restores on exit)
-    #[arg(short, long, default_value = "true")]
+    #[arg(short, long, default_value = "true", num_args = 0..=1, default_missing_value = "true", action = ArgAction::Set)]
     workspaces: bool,
 
+    /// Leave this workspace on its output instead of moving it to the source (repeatable)
+    #[arg(long, value_name = "NAME")]
+    keep_workspace: Vec<String>,
+
+    /// Only duplicate the source: never talk to sway or write workspace state, so it works on any wlroots compositor
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "capture_region_from_sway_output",
+            "state_file",
+            "reconnect_sway",
+            "idle_detection_via_sway",
+            "sway_events",
+            "blank_source",
+        ]
+    )]
+    mirror_only: bool,
+
+    /// When mirroring to all other outputs, skip outputs sway reports as inactive
+    #[arg(long, default_value = "true", action = ArgAction::Set)]
+    connected_only: bool,
+
+    /// Multiply the mirrored colors by this factor, e.g. 1.3 for a dim projector
+    #[arg(long, value_name = "FACTOR", default_value = "1.0")]
+    brightness: f32,
+
+    /// Scale the mirrored colors' distance from mid grey by this factor
+    #[arg(long, value_name = "FACTOR", default_value = "1.0")]
+    contrast: f32,
+
+    /// Gamma correct the mirrored colors; values above 1.0 brighten midtones
+    #[arg(long, value_name = "GAMMA", default_value = "1.0")]
+    gamma: f32,
+
+    /// How the source is sampled onto targets (default: nearest for center, linear otherwise)
+    #[arg(long, value_enum)]
+    filter_mode: Option<FilterModeArg>,
+
+    /// Color filter for accessibility demos and design review
+    #[arg(long, value_enum, default_value = "none")]
+    filter: FilterArg,
+
+    /// Supersample the source at this factor before downscaling (1.0 = off, max 2.0)
+    #[arg(long, value_name = "FACTOR", default_value = "1.0")]
+    render_scale: f32,
+
+    /// Sample through generated mipmaps for smoother heavy downscaling
+    #[arg(long)]
+    mipmap: bool,
+
+    /// Lay out the capture in the source's logical size (device pixels / output scale)
+    #[arg(long)]
+    clamp_source_to_output: bool,
+
+    /// Layer-surface namespace for all mirror surfaces [default: sway-mirror:SOURCE->TARGET]
+    #[arg(long)]
+    namespace: Option<String>,
+
+    /// Input region of the mirror surfaces
+    #[arg(long, value_enum, default_value = "empty")]
+    input_region: InputRegionArg,
+
+    /// On exit, report the distribution of intervals between captured frames
+    #[arg(long)]
+    jitter_report: bool,
+
+    /// Config file with defaults for these options (default: $XDG_CONFIG_HOME/sway-mirror/config.toml)
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip)]
+    config: Option<std::path::PathBuf>,
+
+    /// Print the effective configuration as JSON and exit
+    #[arg(long)]
+    #[serde(skip)]
+    config_dump: bool,
+
     /// Stop a running sway-mirror instance
     #[arg(long)]
+    #[serde(skip)]
     stop: bool,
+
+    /// Run in the background once started, logging to $XDG_STATE_HOME/sway-mirror.log
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "list",
+            "capabilities",
+            "probe_source_formats",
+            "config_dump",
+            "stop",
+            "status",
+            "stdout",
+        ]
+    )]
+    daemon: bool,
+
+    /// Show whether sway-mirror is running and what it is mirroring
+    #[arg(long)]
+    #[serde(skip)]
+    status: bool,
+
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+
+    /// Seconds --stop waits for the instance to exit and for workspaces to be restored
+    #[arg(long, value_name = "SECS", default_value = "5")]
+    restore_timeout: u64,
+
+    /// Force the captured dmabuf to be imported as this format (e.g. XRGB8888 or XR24)
+    #[arg(long, value_name = "FOURCC", value_parser = format::parse_fourcc)]
+    #[serde(serialize_with = "format::serialize_fourcc")]
+    fourcc_override: Option<u32>,
+
+    /// Capture protocol to use
+    #[arg(long, value_enum, default_value = "auto")]
+    capture_backend: CaptureBackendArg,
+
+    /// Ask the capture backend for this format where it can negotiate (e.g. XRGB8888)
+    #[arg(long, value_name = "FOURCC", value_parser = format::parse_fourcc)]
+    #[serde(serialize_with = "format::serialize_fourcc")]
+    prefer_format: Option<u32>,
+
+    /// On dmabuf import/render failure, retry with the next matching EGL config
+    #[arg(long)]
+    retry_egl_config: bool,
+
+    /// Order of target outputs for layouts, left to right / row-major (e.g. DP-1,DP-2,DP-3)
+    #[arg(long, value_name = "OUTPUTS", value_delimiter = ',')]
+    output_order: Vec<String>,
+
+    /// Show sources that fit on a target 1:1 (center) and scale larger ones down (fit),
+    /// re-evaluated every frame; overrides --scale
+    #[arg(long)]
+    auto_scale_mode_by_content: bool,
+
+    /// Mirror only this rectangle of the source (in buffer pixels), scaled up to the targets
+    #[arg(long, value_name = "X,Y,WxH", value_parser = parse_region)]
+    region: Option<(u32, u32, u32, u32)>,
+
+    /// Crop the capture to the source's output rect as reported by sway (drops overscan)
+    #[arg(long)]
+    capture_region_from_sway_output: bool,
+
+    /// Tell the compositor only the drawn region changed, not the whole surface
+    #[arg(long)]
+    surface_damage: bool,
+
+    /// Print GL/EGL errors and KHR_debug messages (slower; for bug reports)
+    #[arg(long)]
+    gl_debug: bool,
+
+    /// Animate the layout when the scale mode changes at runtime instead of jumping
+    #[arg(long)]
+    animate_transitions: bool,
+
+    /// Write the first rendered frame's raw bytes, with a format header, to this file
+    #[arg(long, value_name = "PATH")]
+    dump_first_frame: Option<std::path::PathBuf>,
+
+    /// Log capture, submit and presentation times of every target commit to this CSV file
+    #[arg(long, value_name = "PATH")]
+    present_feedback_log: Option<std::path::PathBuf>,
+
+    /// Show each target's frame rate and capture-to-swap latency in its top-left corner
+    #[arg(long)]
+    show_stats: bool,
+
+    /// Render at most once per refresh of the slowest target, dropping extra captures
+    #[arg(long)]
+    swap_on_vblank_only: bool,
+
+    /// Draw every target first, then commit them all together before one flush
+    #[arg(long)]
+    commit_coalescing: bool,
+
+    /// Render on a dedicated thread while this one captures and dispatches Wayland events
+    #[arg(long)]
+    render_thread: bool,
+
+    /// Discard this many frames after starting, before rendering the first one
+    #[arg(long, value_name = "N", default_value = "1")]
+    warmup_frames: u32,
+
+    /// Only render every Nth captured frame, dropping the others
+    #[arg(long, value_name = "N", default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    capture_every_nth: u64,
+
+    /// Give up on a capture the compositor hasn't delivered after MS milliseconds and request another
+    #[arg(long, value_name = "MS", default_value = "1000", value_parser = clap::value_parser!(u64).range(1..))]
+    frame_timeout_ms: u64,
+
+    /// Present frames on a steady cadence instead of as soon as they are captured, every MS
+    /// milliseconds (default: the source's refresh period); trades latency for smoothness
+    #[arg(long, value_name = "MS", num_args = 0..=1, default_missing_value = "0")]
+    smooth: Option<u64>,
+
+    /// Render at most N frames per second to save power; 0 is uncapped
+    #[arg(long, value_name = "N", default_value = "0")]
+    max_fps: u32,
+
+    /// Blend roughly the last K captured frames into each drawn frame, for smoother low-fps output
+    #[arg(long, value_name = "K", default_value = "1", value_parser = clap::value_parser!(u32).range(1..=64))]
+    motion_blur: u32,
+
+    /// Show a fixed source rectangle on one target, e.g. --tile DP-1=0,0,960,540 (repeatable)
+    #[arg(long, value_name = "OUTPUT=X,Y,W,H", value_parser = parse_tile)]
+    tile: Vec<TileArg>,
+
+    /// Capture and import one frame before moving workspaces, aborting if that fails
+    #[arg(long, default_value = "true", action = ArgAction::Set)]
+    verify_source_capturable: bool,
+
+    /// Allow the source to be a target too, mirroring it onto itself (a feedback loop; for testing)
+    #[arg(long)]
+    allow_self_mirror: bool,
+
+    /// Where to keep the workspace state used to restore workspaces (pass the same to --stop)
+    #[arg(long, value_name = "PATH")]
+    state_file: Option<std::path::PathBuf>,
+
+    /// Retry sway IPC calls with backoff when sway is briefly unreachable, e.g. during a reload
+    #[arg(long)]
+    reconnect_sway: bool,
+
+    /// On SIGUSR1, save the next rendered frame as a timestamped PNG in this directory
+    #[arg(long, value_name = "DIR")]
+    snapshot_on_signal: Option<std::path::PathBuf>,
+
+    /// Follow `swaymsg output ... scale` changes live, resizing the mirror to the new scale
+    #[arg(long)]
+    handle_output_scale_change: bool,
+
+    /// Write raw RGBA frames to stdout for piping into other tools; messages go to stderr
+    #[arg(long)]
+    stdout: bool,
+
+    /// Apply a 1D `.cube` calibration LUT on one target, e.g. --lut DP-1=dp1.cube (repeatable)
+    #[arg(long, value_name = "OUTPUT=PATH", value_parser = parse_lut)]
+    lut: Vec<LutArg>,
+
+    /// Fill every target with its background once before tearing the mirror down
+    #[arg(long)]
+    blank_on_exit: bool,
+
+    /// Pause capturing and blank the targets while the source's workspace has no windows
+    #[arg(long)]
+    idle_detection_via_sway: bool,
+
+    /// Turn the source's display off while mirroring (dims it instead if it then stops producing frames)
+    #[arg(long)]
+    blank_source: bool,
+
+    /// Follow sway's output and workspace events: move new workspaces to the source and pick up output changes right away
+    #[arg(long)]
+    sway_events: bool,
+
+    /// Color temperature of the mirror, for all targets or one (repeatable: -T 5000 -T DP-7=5500)
+    #[arg(short = 'T', long, value_name = "[OUTPUT=]KELVIN", value_parser = parse_temperature)]
+    temperature: Vec<TemperatureArg>,
+
+    /// Sleep this many milliseconds after each capture completes (testing aid)
+    #[arg(long, value_name = "MS", hide = true)]
+    #[serde(skip)]
+    simulate_latency: Option<u64>,
 }
 
+/// Trial captures of the source before giving up (--verify-source-capturable)
+const PREFLIGHT_ATTEMPTS: u32 = 3;
+
+/// Source value that mirrors the output focused at startup
+const FOCUSED_SOURCE: &str = "@focused";
+
+/// Length of the viewport animation enabled by --animate-transitions
+const SCALE_TRANSITION_DURATION: Duration = Duration::from_millis(200);
+
+/// A mirror surface closed again this soon after being recreated is given up on
+const SURFACE_RECREATE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Brightness the source is dimmed to when it can't be turned off (--blank-source)
+const SOURCE_DIM_LEVEL: f32 = 0.1;
+
+/// Least time `--stop` gives its own restore once the instance failed to exit in time
+const FALLBACK_RESTORE_TIME: Duration = Duration::from_secs(2);
+
+/// How often to poll sway for target outputs being powered off
+const POWER_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
 fn write_pid_file() -> Result<()> {
     let pid_file = get_pid_file_path();
     let mut file = fs::File::create(&pid_file)?;
@@ -108,7 +959,56 @@ fn is_sway_mirror_process(pid: i32) -> bool {
     false
 }
 
-fn stop_running_instance() -> Result<()> {
+/// PID of the running instance, if the PID file points at a live sway-mirror
+fn running_instance_pid() -> Option<i32> {
+    let pid: i32 = fs::read_to_string(get_pid_file_path())
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    is_sway_mirror_process(pid).then_some(pid)
+}
+
+fn print_status(json: bool) -> Result<()> {
+    let Some(pid) = running_instance_pid() else {
+        if json {
+            println!("{}", serde_json::json!({ "running": false }));
+        } else {
+            println!("sway-mirror is not running");
+        }
+        process::exit(1);
+    };
+
+    if json {
+        let mut value = match SessionStatus::load() {
+            Ok(status) => serde_json::to_value(&status)?,
+            Err(e) => {
+                eprintln!("Warning: {}", e);
+                serde_json::json!({})
+            }
+        };
+        value["running"] = true.into();
+        value["pid"] = pid.into();
+        println!("{}", value);
+        return Ok(());
+    }
+
+    println!("sway-mirror is running (PID {})", pid);
+    match SessionStatus::load() {
+        Ok(status) => {
+            println!("  Source: {}", status.source);
+            println!("  Targets: {}", status.targets.join(", "));
+            println!("  Scale: {:?}", status.scale);
+            println!("  Cursor: {}", status.cursor);
+            println!("  Workspaces moved: {}", status.workspaces);
+            println!("  Uptime: {}", status::format_uptime(status.uptime()));
+        }
+        Err(e) => eprintln!("Warning: {}", e),
+    }
+    Ok(())
+}
+
+fn stop_running_instance(timeout: Duration) -> Result<()> {
     let pid_file = get_pid_file_path();
     let pid_str = fs::read_to_string(&pid_file)
         .map_err(|_| anyhow::anyhow!("No running sway-mirror instance found (no PID file)"))?;
@@ -128,40 +1028,124 @@ fn stop_running_instance() -> Result<()> {
     }
 
     // Send SIGTERM
-    unsafe {
-        if libc::kill(pid, libc::SIGTERM) == 0 {
-            println!("Sent stop signal to sway-mirror (PID {})", pid);
-
-            // Wait a moment for process to exit
-            std::thread::sleep(std::time::Duration::from_millis(100));
-
-            // Restore workspaces from saved state file
-            if let Err(e) = WorkspaceState::restore_from_file() {
-                eprintln!("Warning: Failed to restore workspaces: {}", e);
-            } else {
-                println!("Restored workspaces to original outputs");
-            }
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        // Process might not exist anymore, clean up PID file
+        remove_pid_file();
+        bail!("Process {} not found (stale PID file removed)", pid);
+    }
+    println!("Sent stop signal to sway-mirror (PID {})", pid);
 
-            // Clean up PID file if still there
-            remove_pid_file();
+    // The instance restores workspaces itself on SIGTERM; wait for it to finish
+    let deadline = Instant::now() + timeout;
+    while is_sway_mirror_process(pid) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    if is_sway_mirror_process(pid) {
+        eprintln!(
+            "Warning: sway-mirror (PID {}) did not exit within {}s",
+            pid,
+            timeout.as_secs()
+        );
+    }
 
-            Ok(())
+    // Restore from the saved state file only if the instance didn't get to it,
+    // with whatever is left of the timeout, but no less than a minimum
+    if WorkspaceState::state_file_exists() {
+        println!("Restoring workspaces from saved state...");
+        let deadline = deadline.max(Instant::now() + FALLBACK_RESTORE_TIME);
+        if let Err(e) = WorkspaceState::restore_from_file(Some(deadline)) {
+            eprintln!("Warning: Failed to restore workspaces: {}", e);
         } else {
-            // Process might not exist anymore, clean up PID file
-            remove_pid_file();
-            bail!("Process {} not found (stale PID file removed)", pid)
+            println!("Restored workspaces to original outputs");
         }
     }
+
+    // Clean up PID and status files if still there
+    remove_pid_file();
+    SessionStatus::remove();
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let (cli, output_configs) = config::parse_with_config::<Cli>()?;
+
+    sway::set_reconnect(cli.reconnect_sway);
+    sway::set_disabled(cli.mirror_only);
+    if let Some(ref path) = cli.state_file {
+        sway::set_state_file(path.clone());
+    }
 
     // Handle --stop
     if cli.stop {
-        return stop_running_instance();
+        return stop_running_instance(Duration::from_secs(cli.restore_timeout));
+    }
+
+    // Handle --status
+    if cli.status {
+        return print_status(false);
+    }
+
+    // Handle the subcommands
+    match cli.command {
+        Some(Command::Ctl { ref command }) => return control::send(&command.join(" ")),
+        Some(Command::Status { json }) => return print_status(json),
+        None => {}
+    }
+
+    // Handle --config-dump
+    if cli.config_dump {
+        println!("{}", serde_json::to_string_pretty(&cli)?);
+        return Ok(());
+    }
+
+    if let Some(ref dir) = cli.snapshot_on_signal {
+        if !dir.is_dir() {
+            bail!("Snapshot directory {} does not exist", dir.display());
+        }
+    }
+
+    if !(cli.brightness >= 0.0 && cli.contrast >= 0.0 && cli.gamma > 0.0) {
+        bail!("--brightness and --contrast must not be negative, and --gamma must be positive");
     }
 
+    // Load LUTs up front, so a bad file fails before anything is changed
+    let luts = cli
+        .lut
+        .iter()
+        .map(|arg| Ok((arg.output.clone(), Arc::new(Lut1d::load(&arg.path)?))))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Per-target scale modes from the config file's [per-output] tables, then
+    // from --target-scale, which wins as the later entry
+    let mut scale_modes = output_configs
+        .iter()
+        .filter_map(|(output, config)| Some((output, config.scale.as_ref()?)))
+        .map(|(output, scale)| {
+            let mode = ScaleModeArg::from_str(scale, true).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid scale {} for {} in the config file: {}",
+                    scale,
+                    output,
+                    e
+                )
+            })?;
+            Ok((output.clone(), ScaleMode::from(mode)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    scale_modes.extend(
+        cli.target_scale
+            .iter()
+            .map(|arg| (arg.output.clone(), ScaleMode::from(arg.mode))),
+    );
+
+    // Frames go to the real stdout, everything we print goes to stderr
+    let stdout_stream = if cli.stdout {
+        Some(stream::take_stdout()?)
+    } else {
+        None
+    };
+
     // Check if already running
     let pid_file = get_pid_file_path();
     if fs::metadata(&pid_file).is_ok() {
@@ -184,52 +1168,218 @@ fn main() -> Result<()> {
         remove_pid_file();
     }
 
+    // Detach before connecting, so the connection and EGL context belong to the daemon
+    let mut readiness = if cli.daemon {
+        Some(daemon::daemonize(&daemon::log_file_path())?)
+    } else {
+        None
+    };
+
     // Connect to Wayland
     let mut conn = wayland::WaylandConnection::connect()?;
 
     // Request xdg_output info
     {
         let qh = conn.queue_handle();
-        request_xdg_outputs(&conn.state, &qh);
+        request_xdg_outputs(&mut conn.state, &qh);
     }
     conn.roundtrip()?;
 
     // Handle --list
     if cli.list {
+        let outputs = conn.state.output_manager.list();
+        if cli.format == ListFormatArg::Json {
+            println!("{}", serde_json::to_string_pretty(&outputs)?);
+            return Ok(());
+        }
         println!("Available outputs:");
-        for output in conn.state.output_manager.list() {
+        for output in outputs {
             println!(
-                "  {} - {} ({}x{})",
-                output.name, output.description, output.width, output.height
+                "  {} - {} ({}x{} @ {}, position {},{}, scale {})",
+                output.name,
+                output.description,
+                output.width,
+                output.height,
+                output.refresh_hz(),
+                output.x,
+                output.y,
+                output.scale
             );
         }
+        if cli.watch {
+            return watch_outputs(&mut conn);
+        }
         return Ok(());
     }
 
-    // Require source
-    let source_name = cli.source.ok_or_else(|| {
+    // Handle --capabilities
+    if cli.capabilities {
+        return capabilities::print_report(&conn);
+    }
+
+    // Handle --probe-source-formats
+    if let Some(ref name) = cli.probe_source_formats {
+        return capabilities::probe_source_formats(
+            &mut conn,
+            name,
+            cli.cursor,
+            cli.capture_backend.into(),
+        );
+    }
+
+    // Before touching anything, so an unsupported compositor doesn't end up with
+    // its workspaces moved and no mirror
+    capabilities::check_required_globals(&conn)?;
+
+    // Require source, which may be a re:/glob: pattern matching exactly one output,
+    // or @focused for whichever output has focus right now
+    let source_pattern = cli.source.as_deref().ok_or_else(|| {
         anyhow::anyhow!("Source output required. Use --list to see available outputs.")
     })?;
+    let source_name = if source_pattern == FOCUSED_SOURCE {
+        if cli.mirror_only {
+            bail!("{} asks sway for the focused output, which --mirror-only rules out; name the source", FOCUSED_SOURCE);
+        }
+        sway::focused_output()?
+    } else {
+        OutputPattern::parse(source_pattern)?.resolve_one(
+            conn.state
+                .output_manager
+                .list()
+                .into_iter()
+                .map(|o| o.name.as_str()),
+        )?
+    };
+    if source_name != source_pattern {
+        println!("Source {} matched {}", source_pattern, source_name);
+    }
 
     // Find source output
-    let source_output = {
+    let (mut source_output, source_scale, source_transform) = {
         let source = conn
             .state
             .output_manager
             .get_by_name(&source_name)
             .ok_or_else(|| anyhow::anyhow!("Source output '{}' not found", source_name))?;
-        source.wl_output.clone()
+        (
+            source.wl_output.clone(),
+            source.scale.max(1),
+            source.transform,
+        )
+    };
+    let background = cli.background.map(|c| c as f32 / 255.0);
+
+    // The source is captured in its device orientation; turn it upright, then
+    // apply --flip and --rotate. Scaling happens last, on the transformed image.
+    let transform = Transform::undoing(source_transform)
+        .then(cli.flip.into())
+        .then(cli.rotate.into());
+    if transform != Transform::Normal {
+        println!("Drawing {} with transform {:?}", source_name, transform);
+    }
+    let source_size = conn
+        .state
+        .output_manager
+        .get_by_name(&source_name)
+        .map(|o| (o.width.max(0) as u32, o.height.max(0) as u32))
+        .unwrap_or_default();
+
+    // Part of the exported buffer that sway actually uses for the source
+    let source_region = if cli.capture_region_from_sway_output {
+        match sway::get_outputs() {
+            Ok(outputs) => outputs
+                .iter()
+                .find(|o| o.name == source_name)
+                .and_then(|o| o.device_size()),
+            Err(e) => {
+                eprintln!("Warning: Could not query sway outputs: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Tiles must lie within what is actually mirrored of the source
+    let (bound_w, bound_h) = source_region.unwrap_or(source_size);
+
+    // A magnified region is clamped to the source rather than rejected
+    let region = match cli.region {
+        Some((x, y, _, _)) if x >= bound_w || y >= bound_h => bail!(
+            "Region origin {},{} lies outside the {}x{} source",
+            x,
+            y,
+            bound_w,
+            bound_h
+        ),
+        Some((x, y, w, h)) => {
+            let clamped = (x, y, w.min(bound_w - x), h.min(bound_h - y));
+            if clamped != (x, y, w, h) {
+                eprintln!(
+                    "Warning: Region {}x{}+{}+{} exceeds the {}x{} source, clamping to {}x{}",
+                    w, h, x, y, bound_w, bound_h, clamped.2, clamped.3
+                );
+            }
+            Some(clamped)
+        }
+        None => None,
     };
+    for tile in &cli.tile {
+        let (x, y, w, h) = tile.region;
+        if u64::from(x) + u64::from(w) > u64::from(bound_w)
+            || u64::from(y) + u64::from(h) > u64::from(bound_h)
+        {
+            bail!(
+                "Tile {}x{}+{}+{} for {} lies outside the {}x{} source",
+                w,
+                h,
+                x,
+                y,
+                tile.output,
+                bound_w,
+                bound_h
+            );
+        }
+    }
+
+    // Outputs sway considers active; None if sway can't be queried
+    let active_outputs: Option<Vec<String>> =
+        if cli.to.is_empty() && cli.connected_only && !cli.mirror_only {
+            match sway::get_outputs() {
+                Ok(outputs) => Some(
+                    outputs
+                        .into_iter()
+                        .filter(|o| o.active)
+                        .map(|o| o.name)
+                        .collect(),
+                ),
+                Err(e) => {
+                    eprintln!("Warning: Could not query sway outputs: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
     // Determine target outputs
-    let target_outputs: Vec<_> = {
+    let mut target_outputs: Vec<_> = {
         if cli.to.is_empty() {
             // All outputs except source
             conn.state
                 .output_manager
-                .list()
+                .list_by_position()
                 .into_iter()
-                .filter(|o| o.name != source_name)
+                .filter(|o| cli.allow_self_mirror || o.name != source_name)
+                .filter(|o| {
+                    let active = active_outputs
+                        .as_ref()
+                        .is_none_or(|names| names.contains(&o.name));
+                    if !active {
+                        println!("Skipping inactive output {}", o.name);
+                    }
+                    active
+                })
                 .map(|o| {
                     (
                         o.name.clone(),
@@ -240,6 +1390,13 @@ fn main() -> Result<()> {
                 })
                 .collect()
         } else {
+            // Drawing the source onto itself feeds the mirror back into its own capture
+            if !cli.allow_self_mirror && cli.to.contains(&source_name) {
+                bail!(
+                    "{} is the source and can't also be a target (see --allow-self-mirror)",
+                    source_name
+                );
+            }
             // Specified targets
             cli.to
                 .iter()
@@ -257,30 +1414,64 @@ fn main() -> Result<()> {
         }
     };
 
-    if target_outputs.is_empty() {
+    // Explicitly ordered outputs go first, the rest keep their geometric order
+    if !cli.output_order.is_empty() {
+        for name in &cli.output_order {
+            if !target_outputs.iter().any(|(n, ..)| n == name) {
+                eprintln!(
+                    "Warning: --output-order names {}, which is not a target",
+                    name
+                );
+            }
+        }
+        target_outputs.sort_by_key(|(name, ..)| {
+            cli.output_order
+                .iter()
+                .position(|n| n == name)
+                .unwrap_or(usize::MAX)
+        });
+    }
+
+    // Streaming to stdout is useful without any target, e.g. on a single screen
+    if cli.allow_self_mirror && target_outputs.iter().any(|(n, ..)| *n == source_name) {
+        eprintln!(
+            "Warning: Mirroring {} onto itself; each frame will capture the previous one",
+            source_name
+        );
+    }
+
+    if target_outputs.is_empty() && stdout_stream.is_none() {
         bail!("No target outputs found");
     }
 
+    for arg in &cli.target_scale {
+        if !target_outputs.iter().any(|(n, ..)| *n == arg.output) {
+            eprintln!(
+                "Warning: --target-scale names {}, which is not a target",
+                arg.output
+            );
+        }
+    }
+
+    for tile in &cli.tile {
+        if !target_outputs.iter().any(|(n, ..)| *n == tile.output) {
+            eprintln!(
+                "Warning: --tile names {}, which is not a target",
+                tile.output
+            );
+        }
+    }
+
     println!("Mirroring {} to: (scale: {:?})", source_name, cli.scale);
     for (name, _, w, h) in &target_outputs {
         println!("  {} ({}x{})", name, w, h);
     }
-
-    // Move all workspaces to source output
-    let workspace_state = if cli.workspaces {
-        match WorkspaceState::capture_and_move_to_source(&source_name) {
-            Ok(state) => {
-                println!("Moved all workspaces to {}", source_name);
-                Some(state)
-            }
-            Err(e) => {
-                eprintln!("Warning: Could not move workspaces: {}", e);
-                None
-            }
-        }
-    } else {
-        None
-    };
+    if let Some(code) = cli.fourcc_override {
+        println!(
+            "Overriding captured format with {}",
+            format::fourcc_name(code)
+        );
+    }
 
     // Set up Ctrl+C handler
     let running = Arc::new(AtomicBool::new(true));
@@ -292,45 +1483,122 @@ fn main() -> Result<()> {
 
     // Initialize EGL
     let wayland_display = conn.connection.backend().display_ptr() as *mut c_void;
-    let mut egl_ctx = EglContext::new(wayland_display)?;
+    let mut egl_ctx = EglContext::new(wayland_display, cli.gl_debug)?;
 
     // Initialize GL (need a surfaceless context first)
     egl_ctx.make_current_surfaceless()?;
     egl_ctx.init_gl()?;
+    egl_ctx.render_scale = cli.render_scale.clamp(1.0, MAX_RENDER_SCALE);
+    egl_ctx.mipmap = cli.mipmap;
+    egl_ctx.motion_blur = cli.motion_blur;
+    if cli.mipmap && cli.motion_blur > 1 {
+        eprintln!("Warning: --mipmap has no effect with --motion-blur");
+    }
+    egl_ctx.crop_anchor = cli.crop_anchor.into();
+    egl_ctx.brightness = cli.brightness;
+    egl_ctx.contrast = cli.contrast;
+    egl_ctx.gamma = cli.gamma;
+    egl_ctx.filter = cli.filter.into();
+    egl_ctx.filter_mode = cli.filter_mode.map(Into::into);
+    if let Some((width, height)) = source_region {
+        println!("Capturing {}x{} region of {}", width, height, source_name);
+        egl_ctx.source_region = Some((0, 0, width, height));
+    }
+    if let Some((x, y, width, height)) = region {
+        println!(
+            "Mirroring {}x{}+{}+{} of {}",
+            width, height, x, y, source_name
+        );
+        egl_ctx.source_region = region;
+    }
+    if cli.surface_damage && !egl_ctx.enable_surface_damage() {
+        eprintln!("Warning: EGL swap_buffers_with_damage not supported, damaging full surfaces");
+    }
+    if cli.animate_transitions {
+        egl_ctx.transition = Some(ViewportTransition::new(SCALE_TRANSITION_DURATION));
+    }
+    if cli.clamp_source_to_output {
+        egl_ctx.source_scale = source_scale as f32;
+    }
 
-    // Create mirror surfaces for each target
-    let mut surfaces: Vec<MirrorSurface> = Vec::new();
-    {
-        let compositor = conn
-            .state
-            .compositor
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("wl_compositor not available"))?;
-        let layer_shell = conn
-            .state
-            .layer_shell
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("zwlr_layer_shell_v1 not available"))?;
+    // Setup capture
+    let capture = Capture::new(&conn.state, cli.capture_backend.into())?;
+    println!("Using {} capture", capture.name());
+    if let Some(code) = cli.prefer_format {
+        // Both protocols dictate the format: export-dmabuf hands over the output's
+        // own buffers, and screencopy announces the one format it will copy into
+        eprintln!(
+            "Warning: {} capture can't negotiate formats, ignoring --prefer-format {}",
+            capture.name(),
+            format::fourcc_name(code)
+        );
+    }
 
-        let qh = conn.queue_handle();
+    // Prove that capture and import work before disturbing the user's workspaces
+    if cli.verify_source_capturable {
+        verify_source_capturable(
+            &mut conn,
+            &capture,
+            &mut egl_ctx,
+            &source_output,
+            &source_name,
+            cli.cursor,
+            cli.fourcc_override,
+        )?;
+    }
 
-        for (name, wl_output, width, height) in &target_outputs {
-            let surface = MirrorSurface::new(
-                compositor,
-                layer_shell,
-                wl_output,
-                &egl_ctx,
-                &qh,
-                *width,
-                *height,
-            )
-            .map_err(|e| anyhow::anyhow!("Failed to create surface for {}: {}", name, e))?;
-            surfaces.push(surface);
+    // Move all workspaces to source output
+    let workspace_state = if cli.workspaces && !cli.mirror_only {
+        let targets: Vec<String> = target_outputs.iter().map(|t| t.0.clone()).collect();
+        match WorkspaceState::capture_and_move_to_source(
+            &source_name,
+            &cli.keep_workspace,
+            &targets,
+        ) {
+            Ok(state) => {
+                println!("Moved all workspaces to {}", source_name);
+                Some(state)
+            }
+            Err(e) => {
+                eprintln!("Warning: Could not move workspaces: {}", e);
+                None
+            }
         }
-    }
+    } else {
+        None
+    };
+
+    // Create mirror surfaces for each target; the template also serves outputs plugged in later
+    let mut template = TargetTemplate::new(&conn.state, conn.queue_handle())?;
+    template.source_name = source_name.clone();
+    template.namespace = cli.namespace.clone();
+    template.click_through = matches!(cli.input_region, InputRegionArg::Empty);
+    template.show_stats = cli.show_stats;
+    template.temperatures = cli.temperature.clone();
+    template.transform = transform;
+    template.background = background;
+    template.tiles = cli.tile.clone();
+    template.luts = luts;
+    template.scale_modes = scale_modes;
+    let template = Arc::new(template);
+    let surfaces = target_outputs
+        .iter()
+        .map(|target| template.build(target, &egl_ctx))
+        .collect::<Result<Vec<MirrorSurface>>>()?;
+
+    // Shared with the surfaces, which pick up changes on their next frame. Targets
+    // always start at their output's scale; later changes need --handle-output-scale-change
+    let mut target_scales: Vec<(String, Arc<Mutex<i32>>)> = surfaces
+        .iter()
+        .map(|s| (s.output_name.clone(), s.pending_scale.clone()))
+        .collect();
+    sync_target_scales(&conn.state.output_manager, &target_scales);
 
     // Wait for surfaces to be configured
-    while surfaces.iter().any(|s| !s.is_configured()) {
+    while surfaces
+        .iter()
+        .any(|s| !s.is_configured() && !s.is_closed())
+    {
         conn.roundtrip()?;
     }
 
@@ -340,64 +1608,511 @@ fn main() -> Result<()> {
     }
     conn.roundtrip()?;
 
-    // Setup dmabuf capture
-    let capture = DmabufCapture::new();
-
     // Write PID file
     write_pid_file()?;
 
+    // Record what we're mirroring for --status
+    let mut session = SessionStatus::new(
+        &source_name,
+        surfaces.iter().map(|s| s.output_name.clone()).collect(),
+        cli.scale,
+        cli.cursor,
+        workspace_state.is_some(),
+    );
+    if let Err(e) = session.save() {
+        eprintln!("Warning: {}", e);
+    }
+
     println!("Mirror active. Press Ctrl+C or use --stop to stop.");
 
     // Main loop
+    let scale_mode = if cli.auto_scale_mode_by_content {
+        ScaleMode::Auto
+    } else {
+        cli.scale.into()
+    };
+    let mut renderer = Renderer::new(egl_ctx, surfaces, scale_mode, cli.retry_egl_config);
+    renderer.dump_path = cli.dump_first_frame.clone();
+    renderer.coalesce_commits = cli.commit_coalescing;
+    if let Some(ref dir) = cli.snapshot_on_signal {
+        let requested = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGUSR1, requested.clone())?;
+        println!(
+            "Send SIGUSR1 (kill -USR1 {}) to save a snapshot to {}",
+            process::id(),
+            dir.display()
+        );
+        renderer.snapshots = Some((dir.clone(), requested));
+    }
+    renderer.stdout_stream = stdout_stream.map(|out| RgbaStream::new(out, running.clone()));
+    if let Some(ref path) = cli.present_feedback_log {
+        match conn.state.presentation.clone() {
+            Some(presentation) => {
+                renderer.presentation_log = Some(PresentationLog::create(
+                    path,
+                    presentation,
+                    conn.state.presentation_clock,
+                    conn.queue_handle(),
+                )?);
+            }
+            None => eprintln!("Warning: wp_presentation not available, no feedback will be logged"),
+        }
+    }
+    if cli.swap_on_vblank_only {
+        // Pace to the slowest target; unknown refresh rates count as slowest
+        let slowest = renderer
+            .surfaces
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, s)| {
+                conn.state
+                    .output_manager
+                    .get_by_name(&s.output_name)
+                    .map_or(0, |o| o.refresh)
+            })
+            .map(|(i, s)| (i, s.output_name.clone()));
+        if let Some((index, name)) = slowest {
+            println!("Pacing renders to {}'s refresh", name);
+            renderer.pace_to(index);
+        }
+    }
+    let mut render_loop = if cli.render_thread {
+        RenderLoop::spawn(renderer, conn.connection.clone())?
+    } else {
+        RenderLoop::Inline(Box::new(renderer))
+    };
+    let mut present_clock = cli.smooth.map(|ms| {
+        let interval = if ms > 0 {
+            Duration::from_millis(ms)
+        } else {
+            // Unknown refresh rates fall back to 60Hz
+            let refresh = conn
+                .state
+                .output_manager
+                .get_by_name(&source_name)
+                .map_or(0, |o| o.refresh);
+            let refresh = if refresh > 0 { refresh } else { 60_000 };
+            Duration::from_secs_f64(1000.0 / refresh as f64)
+        };
+        PresentClock::new(interval)
+    });
+    if let Some(ref clock) = present_clock {
+        println!(
+            "Presenting every {:.1}ms",
+            clock.interval().as_secs_f64() * 1000.0
+        );
+    }
+    // The latest capture, waiting for the present clock; a newer one replaces it
+    let mut held_frame: Option<CapturedFrame> = None;
+    let min_render_interval =
+        (cli.max_fps > 0).then(|| Duration::from_secs_f64(1.0 / cli.max_fps as f64));
+    if cli.max_fps > 0 {
+        println!("Rendering at most {} fps", cli.max_fps);
+    }
+    let mut last_render: Option<Instant> = None;
+    let mut capture_intervals = cli.jitter_report.then(IntervalStats::new);
+    let mut frames_captured: u64 = 0;
+    let frame_timeout = Duration::from_millis(cli.frame_timeout_ms);
+    // An idle output sends no frames either, so only report the first timeout in a row
+    let mut timed_out = false;
+    let mut warmup_frames = cli.warmup_frames;
+    let mut idle = cli
+        .idle_detection_via_sway
+        .then(|| IdleDetector::new(&source_name));
+
+    // Outputs plugged in later become targets if they would have been at startup
+    let mut tracker = TargetTracker::new(&conn.state.output_manager, &target_outputs);
+    let wanted = |name: &str| {
+        if cli.to.is_empty() {
+            cli.allow_self_mirror || name != source_name
+        } else {
+            cli.to.iter().any(|n| n == name)
+        }
+    };
+    let mut source_lost = false;
+    // Sway's events, read on a background thread that ends when this is dropped
+    let sway_events = if cli.sway_events {
+        match SwayEvents::subscribe() {
+            Ok(events) => Some(events),
+            Err(e) => {
+                eprintln!("Warning: Could not subscribe to sway events: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut strays_pending = false;
+    // Polled on a background thread, so a slow sway never stalls capturing or drawing
+    let power_poll = if cli.mirror_only {
+        None
+    } else {
+        match PowerPoll::spawn(POWER_POLL_INTERVAL) {
+            Ok(poll) => Some(poll),
+            Err(e) => {
+                eprintln!("Warning: Could not poll sway for output power: {}", e);
+                None
+            }
+        }
+    };
+
+    // Settings changed while running (`sway-mirror ctl ...`)
+    let control = match ControlSocket::bind() {
+        Ok(control) => Some(control),
+        Err(e) => {
+            eprintln!("Warning: Runtime control is unavailable: {:#}", e);
+            None
+        }
+    };
+    let mut cursor = cli.cursor;
+    let mut paused = false;
+
+    // Turn the source off now that mirroring is set up
+    let mut source_off = false;
+    if cli.blank_source {
+        match sway::set_output_power(&source_name, false) {
+            Ok(()) => {
+                println!("Turned {} off", source_name);
+                source_off = true;
+            }
+            Err(e) => eprintln!("Warning: Could not turn {} off: {}", source_name, e),
+        }
+    }
+    // Whether a frame arrived since; a source that is truly off may produce none
+    let mut frame_while_off = false;
+    let mut source_dimmer = None;
+    // When each target's surface was last recreated after the compositor closed it
+    let mut recreated_at: HashMap<String, Instant> = HashMap::new();
+
+    // One capture is always in flight, so the compositor produces the next frame
+    // while the current one is being rendered
+    let qh = conn.queue_handle();
+    let mut pending = Some(capture.request_frame(&conn.state, &source_output, &qh, cursor)?);
+    let mut requested_at = Instant::now();
+    // Setup succeeded; the process that started the daemon can return now
+    if let Some(ref mut readiness) = readiness {
+        readiness.notify();
+    }
     while running.load(Ordering::SeqCst) {
-        // Check for resize
-        for surface in &mut surfaces {
-            surface.resize_if_needed();
+        for event in sway_events.iter().flat_map(|events| events.take()) {
+            match event {
+                // Read the outputs coming and going now rather than on the next frame
+                SwayEvent::Output => {
+                    conn.roundtrip()?;
+                    if let Some(poll) = &power_poll {
+                        poll.poll_now();
+                    }
+                }
+                SwayEvent::Workspace => strays_pending = true,
+            }
         }
 
-        // Request frame capture
-        {
-            let dmabuf_manager =
-                conn.state.dmabuf_manager.as_ref().ok_or_else(|| {
-                    anyhow::anyhow!("zwlr_export_dmabuf_manager_v1 not available")
-                })?;
-            let qh = conn.queue_handle();
-            capture.request_frame(dmabuf_manager, &source_output, &qh, cli.cursor);
+        // Pause targets whose display sway reports as powered off
+        if let Some(power) = power_poll.as_ref().and_then(PowerPoll::take) {
+            render_loop.set_power(power)?;
+        }
+
+        // Follow targets being unplugged and plugged in (or disabled and enabled)
+        let removed = tracker.take_removed(&conn.state.output_manager);
+        for name in &removed {
+            println!("{} disconnected, no longer mirroring to it", name);
+            render_loop.remove_surface(name)?;
+            target_scales.retain(|(n, _)| n != name);
+        }
+        let mut added = tracker.take_added(&conn.state.output_manager, wanted);
+        for target in &added {
+            println!("{} connected, mirroring to it", target.0);
         }
 
-        // Wait for frame
+        // Replace surfaces the compositor closed on outputs that are still there
+        let closed = std::mem::take(&mut conn.state.closed_surfaces);
+        for name in &closed {
+            let Some(target) = tracker.get(&conn.state.output_manager, name) else {
+                continue;
+            };
+            render_loop.remove_surface(name)?;
+            target_scales.retain(|(n, _)| n != name);
+            let now = Instant::now();
+            if recreated_at
+                .insert(name.clone(), now)
+                .is_some_and(|at| now - at < SURFACE_RECREATE_BACKOFF)
+            {
+                eprintln!(
+                    "Warning: The compositor keeps closing the mirror on {}, giving up on it",
+                    name
+                );
+                tracker.forget(name);
+                continue;
+            }
+            println!("Mirror on {} was closed, recreating it", name);
+            added.push(target);
+        }
+
+        let mut added_scales = Vec::new();
+        for target in &added {
+            let scale = Arc::new(Mutex::new(1));
+            added_scales.push((target.0.clone(), scale.clone()));
+            let (template, target) = (template.clone(), target.clone());
+            render_loop.add_surface(Box::new(move |egl_ctx| {
+                let mut surface = template.build(&target, egl_ctx)?;
+                surface.pending_scale = scale;
+                Ok(surface)
+            }))?;
+        }
+        sync_target_scales(&conn.state.output_manager, &added_scales);
+        target_scales.extend(added_scales);
+        if !removed.is_empty() || !added.is_empty() || !closed.is_empty() {
+            session.targets = tracker.names();
+            if let Err(e) = session.save() {
+                eprintln!("Warning: {}", e);
+            }
+        }
+
+        // Pause while the source is unplugged, and pick it up again when it returns
+        match conn.state.output_manager.get_by_name(&source_name) {
+            None => {
+                if !source_lost {
+                    println!("{} disconnected, pausing", source_name);
+                    source_lost = true;
+                    if let Some(frame) = pending.take() {
+                        capture.abandon(frame);
+                    }
+                    render_loop.clear()?;
+                    held_frame = None;
+                }
+                // Unlike dispatch, this reads the outputs coming and going
+                conn.roundtrip()?;
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Some(output) if source_lost => {
+                println!("{} reconnected, resuming", source_name);
+                source_lost = false;
+                source_output = output.wl_output.clone();
+                // Sway moved the workspaces away when the source went
+                if let Some(ref state) = workspace_state {
+                    match state.move_all_to_source() {
+                        Ok(()) => println!("Moved all workspaces back to {}", source_name),
+                        Err(e) => eprintln!("Warning: Could not move workspaces back: {}", e),
+                    }
+                }
+                pending = Some(capture.request_frame(&conn.state, &source_output, &qh, cursor)?);
+                requested_at = Instant::now();
+            }
+            Some(_) => {}
+        }
+
+        // Workspaces created on (or moved to) other outputs belong on the source too
+        if std::mem::take(&mut strays_pending) {
+            if let Some(ref state) = workspace_state {
+                match state.move_strays_to_source() {
+                    Ok(0) => {}
+                    Ok(n) => println!("Moved {} new workspace(s) to {}", n, source_name),
+                    Err(e) => eprintln!("Warning: Could not move new workspaces: {}", e),
+                }
+            }
+        }
+
+        // Apply settings sent over the control socket
+        let commands = control.as_ref().map(|c| c.poll()).unwrap_or_default();
+        for command in &commands {
+            match *command {
+                ControlCommand::Scale(scale) => {
+                    println!("Scale mode: {:?}", scale);
+                    render_loop.apply(Setting::ScaleMode(scale.into()))?;
+                    session.scale = scale;
+                }
+                ControlCommand::Cursor(on) => {
+                    println!("Cursor {}", if on { "shown" } else { "hidden" });
+                    cursor = on;
+                    session.cursor = on;
+                }
+                ControlCommand::Rotate(rotate) => {
+                    let transform = Transform::undoing(source_transform)
+                        .then(cli.flip.into())
+                        .then(rotate.into());
+                    println!("Drawing {} with transform {:?}", source_name, transform);
+                    render_loop.apply(Setting::Transform(transform))?;
+                }
+                ControlCommand::Pause if !paused => {
+                    println!("Paused");
+                    paused = true;
+                    render_loop.clear()?;
+                    held_frame = None;
+                }
+                ControlCommand::Resume if paused => {
+                    println!("Resumed");
+                    paused = false;
+                }
+                ControlCommand::Pause | ControlCommand::Resume => {}
+            }
+        }
+        if !commands.is_empty() {
+            if let Err(e) = session.save() {
+                eprintln!("Warning: {}", e);
+            }
+        }
+        if paused {
+            conn.dispatch()?;
+            std::thread::sleep(Duration::from_millis(100));
+            requested_at = Instant::now();
+            continue;
+        }
+
+        // Stop capturing while the source shows an empty workspace
+        if let Some(ref mut idle) = idle {
+            match idle.poll() {
+                Some(true) => {
+                    println!("{} has no windows, pausing", source_name);
+                    render_loop.clear()?;
+                    held_frame = None;
+                }
+                Some(false) => println!("{} has windows again, resuming", source_name),
+                None => {}
+            }
+            if idle.is_paused() {
+                conn.dispatch()?;
+                std::thread::sleep(Duration::from_millis(100));
+                requested_at = Instant::now();
+                continue;
+            }
+        }
+
+        // Hold off until the frame rate cap allows the next render
+        if let (Some(interval), Some(last)) = (min_render_interval, last_render) {
+            if sleep_until(last + interval, &running) {
+                // Time spent throttled doesn't count against the compositor
+                requested_at = Instant::now();
+            }
+        }
+
+        // Wait for the frame in flight; a compositor that never answers (e.g. the
+        // source was disabled) must not keep us from noticing, or from shutting down
         while !capture.is_done() && running.load(Ordering::SeqCst) {
+            if present_held_frame(&mut present_clock, &mut held_frame, &mut render_loop)? {
+                last_render = Some(Instant::now());
+            }
+            if requested_at.elapsed() >= frame_timeout {
+                if !timed_out {
+                    eprintln!(
+                        "Warning: No frame from {} after {} ms, requesting a new one",
+                        source_name, cli.frame_timeout_ms
+                    );
+                    timed_out = true;
+                }
+                if source_off && !frame_while_off {
+                    source_dimmer = dim_instead_of_blank(&mut conn, &source_name, &source_output)?;
+                    source_off = false;
+                }
+                if let Some(frame) = pending.take() {
+                    capture.abandon(frame);
+                }
+                break;
+            }
             conn.roundtrip()?;
         }
 
+        // Shutting down: a new request would reset the capture state while the
+        // old frame's events may still arrive, so give up on it instead
+        if !running.load(Ordering::SeqCst) {
+            if let Some(frame) = pending.take() {
+                capture.abandon(frame);
+            }
+            break;
+        }
+
+        // Take the frame and immediately request the next one
+        let (frame, next) = capture.request_next_frame(&conn.state, &source_output, &qh, cursor)?;
+        pending = Some(next);
+        requested_at = Instant::now();
+        conn.flush()?;
+
+        // Emulate a slow compositor for exercising the frame pacing logic
+        if let Some(ms) = cli.simulate_latency {
+            if frame.is_some() {
+                std::thread::sleep(Duration::from_millis(ms));
+            }
+        }
+
         // Render to all targets
-        if let Some(frame) = capture.take_frame() {
-            let scale_mode: ScaleMode = cli.scale.into();
-            for surface in &surfaces {
-                egl_ctx.render_frame(
-                    &frame,
-                    surface.egl_window_surface,
-                    surface.width as i32,
-                    surface.height as i32,
-                    scale_mode,
-                )?;
-                surface.commit();
+        if let Some(mut frame) = frame {
+            timed_out = false;
+            frame_while_off |= source_off;
+            if let Some(code) = cli.fourcc_override {
+                frame.format = code;
+            }
+            if let Some(ref mut stats) = capture_intervals {
+                stats.record(frame.ready_at);
+            }
+            // The first frames can be stale or partial; drop them, leaving targets black
+            if warmup_frames > 0 {
+                warmup_frames -= 1;
+                conn.dispatch()?;
+                continue;
             }
+            // Decimate by count; skipped frames are dropped, releasing their fds
+            let skip = !frames_captured.is_multiple_of(cli.capture_every_nth);
+            frames_captured += 1;
+            if present_clock.is_some() && !skip {
+                held_frame = Some(frame);
+            } else if !skip {
+                render_loop.submit(frame)?;
+                last_render = Some(Instant::now());
+            } else if cli.motion_blur > 1 {
+                render_loop.accumulate(frame)?;
+            }
+        }
+
+        if present_held_frame(&mut present_clock, &mut held_frame, &mut render_loop)? {
+            last_render = Some(Instant::now());
         }
 
         conn.dispatch()?;
+        if cli.handle_output_scale_change {
+            sync_target_scales(&conn.state.output_manager, &target_scales);
+        }
     }
 
     // Cleanup on exit
     println!("\nStopping mirror...");
 
+    if let Some(ref stats) = capture_intervals {
+        stats.print_summary("Capture intervals");
+    }
+
+    let mut renderer = render_loop.finish()?;
+    if cli.blank_on_exit {
+        // Show the background until the compositor has taken the surfaces down
+        match renderer.clear() {
+            Ok(()) => {
+                let _ = conn.roundtrip();
+            }
+            Err(e) => eprintln!("Warning: Failed to blank targets: {}", e),
+        }
+    }
+
     // Explicitly drop surfaces to destroy layer surfaces
-    drop(surfaces);
+    drop(renderer.surfaces);
     // Flush destroy commands to compositor
     let _ = conn.roundtrip();
 
-    // Remove PID file
+    // Remove PID and status files
     remove_pid_file();
+    SessionStatus::remove();
+
+    // Stop following sway before the restore moves workspaces around
+    drop(sway_events);
+
+    // Light the source up again; dropping the dimmer restores its gamma tables
+    if source_off {
+        match sway::set_output_power(&source_name, true) {
+            Ok(()) => println!("Turned {} back on", source_name),
+            Err(e) => eprintln!("Warning: Could not turn {} back on: {}", source_name, e),
+        }
+    }
+    if source_dimmer.take().is_some() {
+        conn.flush()?;
+    }
 
     // Restore workspaces (use in-memory state if available, otherwise cleanup state file)
     if let Some(state) = workspace_state {
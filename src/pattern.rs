@@ -0,0 +1,79 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+
+/// An output name, or a pattern over output names given as `re:REGEX` or `glob:GLOB`
+pub enum OutputPattern {
+    Exact(String),
+    /// The pattern as written, and the regex it compiles to
+    Regex(String, Regex),
+}
+
+impl OutputPattern {
+    pub fn parse(s: &str) -> Result<Self> {
+        if let Some(re) = s.strip_prefix("re:") {
+            let regex = Regex::new(&format!("^(?:{})$", re))
+                .with_context(|| format!("Invalid output regex '{}'", re))?;
+            Ok(Self::Regex(s.to_string(), regex))
+        } else if let Some(glob) = s.strip_prefix("glob:") {
+            Ok(Self::Regex(s.to_string(), glob_to_regex(glob)?))
+        } else {
+            Ok(Self::Exact(s.to_string()))
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Exact(exact) => exact == name,
+            Self::Regex(_, regex) => regex.is_match(name),
+        }
+    }
+
+    /// Resolve to the one name matching the pattern, erroring on zero or several
+    pub fn resolve_one<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Result<String> {
+        let pattern = match self {
+            // Plain names are looked up as-is, so the usual "not found" error applies
+            Self::Exact(name) => return Ok(name.clone()),
+            Self::Regex(pattern, _) => pattern,
+        };
+
+        let mut matches: Vec<&str> = names.into_iter().filter(|n| self.matches(n)).collect();
+        matches.sort();
+        match matches.as_slice() {
+            [name] => Ok(name.to_string()),
+            [] => bail!("No output matches '{}'", pattern),
+            _ => bail!(
+                "Several outputs match '{}': {}",
+                pattern,
+                matches.join(", ")
+            ),
+        }
+    }
+}
+
+/// Translate a shell glob (`*`, `?`, `[...]`) into an anchored regex
+fn glob_to_regex(glob: &str) -> Result<Regex> {
+    let mut re = String::from("^");
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => re.push_str(".*"),
+            '?' if !in_class => re.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                re.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                re.push(']');
+            }
+            '!' if in_class && re.ends_with('[') => re.push('^'),
+            _ if in_class => re.push(c),
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if in_class {
+        bail!("Unterminated '[' in output glob '{}'", glob);
+    }
+    re.push('$');
+    Regex::new(&re).with_context(|| format!("Invalid output glob '{}'", glob))
+}
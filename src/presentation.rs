@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use nix::libc;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use wayland_client::{protocol::wl_surface, Connection, Dispatch, QueueHandle, WEnum};
+use wayland_protocols::wp::presentation_time::client::{
+    wp_presentation::WpPresentation,
+    wp_presentation_feedback::{self, WpPresentationFeedback},
+};
+
+use crate::wayland::AppState;
+
+type LogWriter = Arc<Mutex<BufWriter<File>>>;
+
+/// Writes one CSV row per target commit, matching presentation feedback back to it
+pub struct PresentationLog {
+    presentation: WpPresentation,
+    qh: QueueHandle<AppState>,
+    clock: libc::clockid_t,
+    writer: LogWriter,
+    next_id: u64,
+}
+
+/// What we knew about a commit when requesting its feedback
+pub struct FeedbackData {
+    id: u64,
+    output: String,
+    /// Time from the capture's Ready event to starting to draw this target
+    capture_to_submit_ms: f64,
+    /// When drawing started, in the presentation clock
+    submitted_ns: i128,
+    writer: LogWriter,
+}
+
+impl PresentationLog {
+    pub fn create(
+        path: &Path,
+        presentation: WpPresentation,
+        clock: u32,
+        qh: QueueHandle<AppState>,
+    ) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(
+            writer,
+            "id,output,capture_to_submit_ms,submit_to_present_ms,refresh_ns,seq,flags"
+        )?;
+        Ok(Self {
+            presentation,
+            qh,
+            clock: clock as libc::clockid_t,
+            writer: Arc::new(Mutex::new(writer)),
+            next_id: 0,
+        })
+    }
+
+    /// Request feedback for the next commit of `surface`, which is about to be drawn
+    pub fn track(&mut self, surface: &wl_surface::WlSurface, output: &str, captured_at: Instant) {
+        let data = FeedbackData {
+            id: self.next_id,
+            output: output.to_string(),
+            capture_to_submit_ms: captured_at.elapsed().as_secs_f64() * 1000.0,
+            submitted_ns: now_ns(self.clock),
+            writer: self.writer.clone(),
+        };
+        self.next_id += 1;
+        self.presentation.feedback(surface, &self.qh, data);
+    }
+}
+
+impl Drop for PresentationLog {
+    fn drop(&mut self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+fn now_ns(clock: libc::clockid_t) -> i128 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    ts.tv_sec as i128 * 1_000_000_000 + ts.tv_nsec as i128
+}
+
+impl Dispatch<WpPresentationFeedback, FeedbackData> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpPresentationFeedback,
+        event: wp_presentation_feedback::Event,
+        data: &FeedbackData,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let row = match event {
+            wp_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                seq_hi,
+                seq_lo,
+                flags,
+            } => {
+                let sec = ((tv_sec_hi as u64) << 32) | tv_sec_lo as u64;
+                let presented_ns = sec as i128 * 1_000_000_000 + tv_nsec as i128;
+                let flags = match flags {
+                    WEnum::Value(flags) => format!("{:?}", flags),
+                    WEnum::Unknown(bits) => format!("0x{:x}", bits),
+                };
+                format!(
+                    "{},{},{:.3},{:.3},{},{},\"{}\"",
+                    data.id,
+                    data.output,
+                    data.capture_to_submit_ms,
+                    (presented_ns - data.submitted_ns) as f64 / 1e6,
+                    refresh,
+                    ((seq_hi as u64) << 32) | seq_lo as u64,
+                    flags
+                )
+            }
+            wp_presentation_feedback::Event::Discarded => format!(
+                "{},{},{:.3},,,,discarded",
+                data.id, data.output, data.capture_to_submit_ms
+            ),
+            _ => return,
+        };
+        if let Ok(mut writer) = data.writer.lock() {
+            let _ = writeln!(writer, "{}", row);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/// White point the source is assumed to be calibrated for; corrections are relative to it
+pub const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+/// Per-channel RGB gains that shift white from 6500K to the given color temperature.
+///
+/// Uses Tanner Helland's blackbody fit, normalized so 6500K is exactly (1, 1, 1).
+pub fn temperature_gain(kelvin: u32) -> [f32; 3] {
+    let white = blackbody_rgb(NEUTRAL_TEMPERATURE as f32);
+    let rgb = blackbody_rgb(kelvin as f32);
+    [rgb[0] / white[0], rgb[1] / white[1], rgb[2] / white[2]]
+}
+
+/// Approximate sRGB color of a blackbody radiator, each channel in 0.0..=1.0
+fn blackbody_rgb(kelvin: f32) -> [f32; 3] {
+    let t = kelvin / 100.0;
+
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let green = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    [red, green, blue].map(|c| c.clamp(0.0, 255.0) / 255.0)
+}
+
+/// Whole-image color transform for accessibility demos and design review
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorFilter {
+    #[default]
+    None,
+    Grayscale,
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+    Invert,
+}
+
+impl ColorFilter {
+    /// The filter as `rgb' = matrix * rgb + offset`, with the 3x3 matrix in row-major order.
+    ///
+    /// Grayscale uses BT.709 luma weights; the color blindness simulations are
+    /// Machado et al. (2009) at full severity.
+    pub fn affine(self) -> ([f32; 9], [f32; 3]) {
+        match self {
+            ColorFilter::None => ([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0], [0.0; 3]),
+            ColorFilter::Grayscale => (
+                [
+                    0.2126, 0.7152, 0.0722, 0.2126, 0.7152, 0.0722, 0.2126, 0.7152, 0.0722,
+                ],
+                [0.0; 3],
+            ),
+            ColorFilter::Protanopia => (
+                [
+                    0.152_286, 1.052_583, -0.204_868, 0.114_503, 0.786_281, 0.099_216, -0.003_882,
+                    -0.048_116, 1.051_998,
+                ],
+                [0.0; 3],
+            ),
+            ColorFilter::Deuteranopia => (
+                [
+                    0.367_322, 0.860_646, -0.227_968, 0.280_085, 0.672_501, 0.047_413, -0.011_820,
+                    0.042_940, 0.968_881,
+                ],
+                [0.0; 3],
+            ),
+            ColorFilter::Tritanopia => (
+                [
+                    1.255_528, -0.076_749, -0.178_779, -0.078_411, 0.930_809, 0.147_602, 0.004_733,
+                    0.691_367, 0.303_900,
+                ],
+                [0.0; 3],
+            ),
+            ColorFilter::Invert => ([-1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, -1.0], [1.0; 3]),
+        }
+    }
+}
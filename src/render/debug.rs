@@ -0,0 +1,131 @@
+use khronos_egl as egl;
+use std::ffi::{c_char, c_void, CStr};
+
+// EGL_KHR_debug
+const EGL_DEBUG_MSG_CRITICAL_KHR: egl::Int = 0x33B9;
+const EGL_DEBUG_MSG_ERROR_KHR: egl::Int = 0x33BA;
+const EGL_DEBUG_MSG_WARN_KHR: egl::Int = 0x33BB;
+const EGL_DEBUG_MSG_INFO_KHR: egl::Int = 0x33BC;
+
+type EglDebugProc = extern "system" fn(
+    error: egl::Enum,
+    command: *const c_char,
+    message_type: egl::Int,
+    thread_label: *mut c_void,
+    object_label: *mut c_void,
+    message: *const c_char,
+);
+type EglDebugMessageControl =
+    extern "system" fn(callback: Option<EglDebugProc>, attrib_list: *const egl::Attrib) -> egl::Int;
+
+unsafe fn lossy(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+extern "system" fn egl_debug_callback(
+    error: egl::Enum,
+    command: *const c_char,
+    message_type: egl::Int,
+    _thread_label: *mut c_void,
+    _object_label: *mut c_void,
+    message: *const c_char,
+) {
+    let kind = match message_type {
+        EGL_DEBUG_MSG_CRITICAL_KHR => "critical",
+        EGL_DEBUG_MSG_ERROR_KHR => "error",
+        EGL_DEBUG_MSG_WARN_KHR => "warning",
+        EGL_DEBUG_MSG_INFO_KHR => "info",
+        _ => "message",
+    };
+    let (command, message) = unsafe { (lossy(command), lossy(message)) };
+    eprintln!("EGL {} in {} (0x{:04x}): {}", kind, command, error, message);
+}
+
+/// Route EGL errors and warnings to stderr via EGL_KHR_debug.
+///
+/// Returns false if the extension is missing.
+pub fn install_egl_callback(egl: &egl::DynamicInstance<egl::EGL1_5>) -> bool {
+    // EGL_KHR_debug is a client extension, listed on EGL_NO_DISPLAY
+    let has_extension = egl
+        .query_string(None, egl::EXTENSIONS)
+        .map(|s| s.to_string_lossy().split(' ').any(|e| e == "EGL_KHR_debug"))
+        .unwrap_or(false);
+    let Some(proc) = egl.get_proc_address("eglDebugMessageControlKHR") else {
+        return false;
+    };
+    if !has_extension {
+        return false;
+    }
+
+    let attribs = [
+        EGL_DEBUG_MSG_CRITICAL_KHR as egl::Attrib,
+        egl::TRUE as egl::Attrib,
+        EGL_DEBUG_MSG_ERROR_KHR as egl::Attrib,
+        egl::TRUE as egl::Attrib,
+        EGL_DEBUG_MSG_WARN_KHR as egl::Attrib,
+        egl::TRUE as egl::Attrib,
+        egl::NONE as egl::Attrib,
+    ];
+    let control: EglDebugMessageControl = unsafe { std::mem::transmute(proc) };
+    control(Some(egl_debug_callback), attribs.as_ptr()) == egl::SUCCESS as egl::Int
+}
+
+extern "system" fn gl_debug_callback(
+    source: gl::types::GLenum,
+    gltype: gl::types::GLenum,
+    id: gl::types::GLuint,
+    severity: gl::types::GLenum,
+    _length: gl::types::GLsizei,
+    message: *const gl::types::GLchar,
+    _user_param: *mut c_void,
+) {
+    let severity = match severity {
+        gl::DEBUG_SEVERITY_HIGH => "high",
+        gl::DEBUG_SEVERITY_MEDIUM => "medium",
+        gl::DEBUG_SEVERITY_LOW => "low",
+        _ => "notification",
+    };
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    };
+    let message = unsafe { lossy(message) };
+    eprintln!(
+        "GL [{}, {} severity, type 0x{:04x}, id {}]: {}",
+        source, severity, gltype, id, message
+    );
+}
+
+/// Route GL debug messages to stderr via GL_KHR_debug.
+///
+/// # Safety
+/// A GL context must be current and the GL functions loaded.
+pub unsafe fn install_gl_callback(extensions: &str) -> bool {
+    if !extensions.split(' ').any(|e| e == "GL_KHR_debug") || !gl::DebugMessageCallback::is_loaded()
+    {
+        return false;
+    }
+    gl::Enable(gl::DEBUG_OUTPUT);
+    // Report errors from inside the offending call, so they interleave with our logs
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    gl::DebugMessageCallback(Some(gl_debug_callback), std::ptr::null());
+    true
+}
+
+/// Print and clear any pending GL errors, naming the step that preceded them
+pub fn check_gl_errors(step: &str) {
+    loop {
+        let error = unsafe { gl::GetError() };
+        if error == gl::NO_ERROR {
+            break;
+        }
+        eprintln!("GL error 0x{:04x} after {}", error, step);
+    }
+}
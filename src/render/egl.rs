@@ -1,36 +1,185 @@
 use anyhow::{bail, Context, Result};
+use drm_fourcc::DrmFourcc;
 use khronos_egl as egl;
+use std::collections::HashMap;
 use std::ffi::c_void;
 
+use crate::capture::dmabuf::DmabufPlane;
 use crate::capture::CapturedFrame;
-use crate::render::ScaleMode;
+use crate::render::color::ColorFilter;
+use crate::render::debug;
+use crate::render::fbo::{FboPool, FboRole};
+use crate::render::stats;
+use crate::render::{
+    compute_viewport, layout_size, resolve_mode, CropAnchor, FilterMode, Lut1d, ScaleMode,
+    TargetParams, Transform, ViewportTransition,
+};
+
+/// Largest accepted --render-scale; at 2x a bilinear downsample is an exact box filter
+pub const MAX_RENDER_SCALE: f32 = 2.0;
 
 pub struct EglContext {
     pub egl: egl::DynamicInstance<egl::EGL1_5>,
     pub display: egl::Display,
     pub context: egl::Context,
     pub config: egl::Config,
+    /// All configs matching our attributes, in EGL preference order
+    pub configs: Vec<egl::Config>,
+    pub config_index: usize,
     // OpenGL state
     pub program: u32,
+    /// Location of the per-surface RGB gain uniform in `program`
+    pub gain_location: i32,
+    /// Location of the texcoord offset/scale uniform in `program`
+    pub tex_rect_location: i32,
+    /// Location of the clip-space rotation uniform in `program`
+    rotation_location: i32,
+    /// Location of the brightness/contrast/inverse gamma uniform in `program`
+    adjust_location: i32,
+    /// Locations of the color filter matrix and offset uniforms in `program`
+    filter_locations: (i32, i32),
+    /// Location of the LUT texcoord scale/offset uniform in `program`; zero disables the LUT
+    lut_coord_location: i32,
+    /// Uploaded LUT textures, keyed by the address of their table
+    lut_textures: HashMap<usize, u32>,
+    /// Converts YUV frames to RGB, sampling the Y and UV planes from units 0 and 1
+    yuv_program: u32,
+    /// Location of the YUYV frame width uniform in `yuv_program`; zero selects NV12
+    packed_width_location: i32,
+    /// Y (or packed YUYV) and UV plane textures of YUV frames
+    yuv_textures: [u32; 2],
     pub vao: u32,
+    /// Quad with GL-native (bottom-up) texcoords for drawing offscreen textures
+    pub blit_vao: u32,
     pub texture: u32,
+    pub fbo_pool: FboPool,
+    /// Supersampling factor; 1.0 renders directly to the surface
+    pub render_scale: f32,
+    /// Divides captured (device pixel) frame sizes into the units used for layout
+    pub source_scale: f32,
+    /// Sample the source through a mipmapped copy for smoother minification
+    pub mipmap: bool,
+    /// Multiplier applied to targets' colors (--brightness); 1.0 leaves them unchanged
+    pub brightness: f32,
+    /// Contrast around mid grey (--contrast); 1.0 leaves it unchanged
+    pub contrast: f32,
+    /// Gamma correction on targets (--gamma); 1.0 leaves it unchanged
+    pub gamma: f32,
+    /// Sampling of the source onto targets; None picks nearest where a target is drawn in Center, linear otherwise
+    pub filter_mode: Option<FilterMode>,
+    /// Color transform applied to targets (--filter)
+    pub filter: ColorFilter,
+    /// Average roughly this many recent frames into each drawn one; 1 is off
+    pub motion_blur: u32,
+    /// Frames blended into the motion blur accumulator so far, and its size
+    blur_frames: u32,
+    blur_size: (i32, i32),
+    /// Which part of the source Fill mode keeps
+    pub crop_anchor: CropAnchor,
+    /// Part of the captured frame to mirror (x, y, width, height in buffer pixels)
+    pub source_region: Option<(u32, u32, u32, u32)>,
+    /// Animate the viewport when the scale mode changes; None switches instantly
+    pub transition: Option<ViewportTransition>,
+    /// Report GL/EGL errors (--gl-debug)
+    pub debug: bool,
+    /// EGL_EXT_image_dma_buf_import_modifiers is available, so imports can name the modifier
+    import_modifiers: bool,
+    /// eglSwapBuffersWithDamage{KHR,EXT}, when partial damage is enabled
+    swap_with_damage: Option<SwapBuffersWithDamage>,
+    /// Image entry points, resolved by init_gl
+    create_image_fn: Option<CreateImageKHR>,
+    destroy_image_fn: Option<DestroyImageKHR>,
+    image_target_fn: Option<ImageTargetTexture2DOES>,
+    /// EGL images of recently seen dmabufs, least recently used first
+    image_cache: Vec<(ImageKey, *mut c_void)>,
+    /// The frame being drawn onto the targets
+    bound: Option<BoundFrame>,
+    /// Viewport (x, y, w, h) and surface size last drawn on each surface, keyed by EGL surface handle
+    last_layouts: HashMap<usize, [i32; 6]>,
 }
 
+#[allow(improper_ctypes_definitions)]
+type SwapBuffersWithDamage =
+    unsafe extern "C" fn(egl::Display, *mut c_void, *const i32, i32) -> egl::Boolean;
+#[allow(improper_ctypes_definitions)]
+type CreateImageKHR = unsafe extern "C" fn(
+    egl::Display,
+    *mut c_void, // EGLContext as raw pointer
+    u32,
+    *mut c_void,
+    *const i32,
+) -> *mut c_void;
+#[allow(improper_ctypes_definitions)]
+type DestroyImageKHR = unsafe extern "C" fn(egl::Display, *mut c_void) -> u32;
+type ImageTargetTexture2DOES = unsafe extern "C" fn(u32, *mut c_void);
+
 // EGL extensions for dmabuf import
 const EGL_LINUX_DMA_BUF_EXT: u32 = 0x3270;
 const EGL_LINUX_DRM_FOURCC_EXT: i32 = 0x3271;
-const EGL_DMA_BUF_PLANE0_FD_EXT: i32 = 0x3272;
-const EGL_DMA_BUF_PLANE0_OFFSET_EXT: i32 = 0x3273;
-const EGL_DMA_BUF_PLANE0_PITCH_EXT: i32 = 0x3274;
+/// Per-plane FD, offset, pitch, modifier low and high attributes for planes 0-2
+const EGL_DMA_BUF_PLANE_ATTRIBS: [[i32; 5]; 3] = [
+    [0x3272, 0x3273, 0x3274, 0x3443, 0x3444],
+    [0x3275, 0x3276, 0x3277, 0x3445, 0x3446],
+    [0x3278, 0x3279, 0x327a, 0x3447, 0x3448],
+];
+/// The buffer's layout is implied by the driver rather than described by a modifier
+const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
 const EGL_WIDTH: i32 = 0x3057;
 const EGL_HEIGHT: i32 = 0x3056;
 const EGL_NO_CONTEXT: *mut c_void = std::ptr::null_mut();
 
+/// Images kept for reuse: a triple-buffered output's three buffers, with room
+/// for the two plane images of each NV12 buffer
+const IMAGE_CACHE_SIZE: usize = 6;
+
+/// Identity of the dmabuf behind an EGL image.
+///
+/// export-dmabuf sends fresh fds every frame, but they refer to the same few
+/// buffers, whose device and inode numbers stay the same.
+#[derive(PartialEq, Eq)]
+struct ImageKey {
+    width: u32,
+    height: u32,
+    format: u32,
+    /// Device, inode, offset, stride and modifier of each plane
+    planes: Vec<(u64, u64, u32, u32, u64)>,
+}
+
+/// The frame `bind_frame` set up for drawing onto each target
+#[derive(Clone, Copy)]
+struct BoundFrame {
+    width: u32,
+    height: u32,
+    /// Texture to sample and the quad matching its orientation; None draws only the background
+    source: Option<(u32, u32)>,
+    /// The texture is an FBO's, stored bottom-up
+    bottom_up: bool,
+    /// The texture has mipmaps, so it is minified with LINEAR_MIPMAP_LINEAR
+    mipmapped: bool,
+}
+
+/// A frame imported for drawing
+struct ImportedFrame {
+    /// Top-down RGB texture of the frame
+    texture: u32,
+}
+
+/// Shader gain for passes that must not alter colors
+const IDENTITY_GAIN: [f32; 3] = [1.0, 1.0, 1.0];
+
+/// Texcoord offset/scale that samples the whole texture
+const FULL_TEX_RECT: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
 impl EglContext {
-    pub fn new(wayland_display: *mut c_void) -> Result<Self> {
+    /// Create a context on the Wayland display; `debug` enables EGL/GL debug output
+    pub fn new(wayland_display: *mut c_void, debug: bool) -> Result<Self> {
         let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
             .context("Failed to load EGL")?;
 
+        if debug && !debug::install_egl_callback(&egl) {
+            eprintln!("Warning: EGL_KHR_debug not available, EGL errors won't be reported");
+        }
+
         // Get EGL display from Wayland
         let display = unsafe { egl.get_display(wayland_display) }
             .ok_or_else(|| anyhow::anyhow!("Failed to get EGL display"))?;
@@ -55,39 +204,127 @@ impl EglContext {
             egl::NONE,
         ];
 
-        let config = egl
-            .choose_first_config(display, &config_attribs)
-            .context("Failed to choose EGL config")?
+        // Keep every matching config so we can fall back if dmabuf import fails
+        let count = egl
+            .matching_config_count(display, &config_attribs)
+            .context("Failed to choose EGL config")?;
+        let mut configs = Vec::with_capacity(count);
+        egl.choose_config(display, &config_attribs, &mut configs)
+            .context("Failed to choose EGL config")?;
+        let config = *configs
+            .first()
             .ok_or_else(|| anyhow::anyhow!("No suitable EGL config found"))?;
 
         // Bind OpenGL ES API
         egl.bind_api(egl::OPENGL_ES_API)
             .context("Failed to bind OpenGL ES API")?;
 
-        // Create context
-        let context_attribs = [
-            egl::CONTEXT_MAJOR_VERSION,
-            2,
-            egl::CONTEXT_MINOR_VERSION,
-            0,
-            egl::NONE,
-        ];
+        let context = Self::create_context(&egl, display, config, debug)?;
 
-        let context = egl
-            .create_context(display, config, None, &context_attribs)
-            .context("Failed to create EGL context")?;
+        let import_modifiers = egl
+            .query_string(Some(display), egl::EXTENSIONS)
+            .map(|s| {
+                s.to_string_lossy()
+                    .split(' ')
+                    .any(|e| e == "EGL_EXT_image_dma_buf_import_modifiers")
+            })
+            .unwrap_or(false);
 
         Ok(Self {
             egl,
             display,
             context,
             config,
+            configs,
+            config_index: 0,
             program: 0,
+            gain_location: -1,
+            tex_rect_location: -1,
+            lut_coord_location: -1,
+            rotation_location: -1,
+            adjust_location: -1,
+            filter_locations: (-1, -1),
+            lut_textures: HashMap::new(),
+            yuv_program: 0,
+            packed_width_location: -1,
+            yuv_textures: [0; 2],
             vao: 0,
+            blit_vao: 0,
             texture: 0,
+            fbo_pool: FboPool::new(),
+            render_scale: 1.0,
+            source_scale: 1.0,
+            mipmap: false,
+            brightness: 1.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            filter_mode: None,
+            filter: ColorFilter::None,
+            motion_blur: 1,
+            blur_frames: 0,
+            blur_size: (0, 0),
+            crop_anchor: CropAnchor::Center,
+            source_region: None,
+            transition: None,
+            debug,
+            import_modifiers,
+            swap_with_damage: None,
+            create_image_fn: None,
+            destroy_image_fn: None,
+            image_target_fn: None,
+            image_cache: Vec::new(),
+            bound: None,
+            last_layouts: HashMap::new(),
         })
     }
 
+    fn create_context(
+        egl: &egl::DynamicInstance<egl::EGL1_5>,
+        display: egl::Display,
+        config: egl::Config,
+        debug: bool,
+    ) -> Result<egl::Context> {
+        let mut context_attribs =
+            vec![egl::CONTEXT_MAJOR_VERSION, 2, egl::CONTEXT_MINOR_VERSION, 0];
+        if debug {
+            // Drivers may only emit KHR_debug messages on debug contexts
+            context_attribs.extend([egl::CONTEXT_OPENGL_DEBUG, egl::TRUE as egl::Int]);
+        }
+        context_attribs.push(egl::NONE);
+
+        egl.create_context(display, config, None, &context_attribs)
+            .context("Failed to create EGL context")
+    }
+
+    /// Recreate the context against the next candidate config.
+    ///
+    /// Returns false when there are no configs left to try. On success the GL
+    /// state is rebuilt, but existing window surfaces still belong to the old
+    /// config and must be recreated by the caller.
+    pub fn switch_to_next_config(&mut self) -> Result<bool> {
+        if self.config_index + 1 >= self.configs.len() {
+            return Ok(false);
+        }
+
+        self.release_current()?;
+        let _ = self.egl.destroy_context(self.display, self.context);
+
+        // GL objects died with the old context
+        self.fbo_pool.reset();
+        self.lut_textures.clear();
+        self.blur_frames = 0;
+        self.bound = None;
+        self.clear_image_cache();
+
+        self.config_index += 1;
+        self.config = self.configs[self.config_index];
+        self.context = Self::create_context(&self.egl, self.display, self.config, self.debug)?;
+
+        self.make_current_surfaceless()?;
+        self.init_gl()?;
+        Ok(true)
+    }
+
     pub fn make_current(&self, surface: egl::Surface) -> Result<()> {
         self.egl
             .make_current(
@@ -100,6 +337,14 @@ impl EglContext {
         Ok(())
     }
 
+    /// Unbind the context from this thread, so another thread can make it current
+    pub fn release_current(&self) -> Result<()> {
+        self.egl
+            .make_current(self.display, None, None, None)
+            .context("Failed to release EGL context")?;
+        Ok(())
+    }
+
     pub fn make_current_surfaceless(&self) -> Result<()> {
         self.egl
             .make_current(self.display, None, None, Some(self.context))
@@ -119,6 +364,61 @@ impl EglContext {
         Ok(surface)
     }
 
+    /// Query an EGL display string such as egl::VENDOR or egl::EXTENSIONS
+    pub fn query_string(&self, name: egl::Int) -> String {
+        self.egl
+            .query_string(Some(self.display), name)
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Query a GL string such as gl::RENDERER (requires init_gl)
+    pub fn gl_string(&self, name: u32) -> String {
+        unsafe {
+            let ptr = gl::GetString(name);
+            if ptr.is_null() {
+                return String::new();
+            }
+            std::ffi::CStr::from_ptr(ptr as *const std::ffi::c_char)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    /// DRM formats the driver can import as dmabufs (EGL_EXT_image_dma_buf_import_modifiers)
+    pub fn dmabuf_formats(&self) -> Vec<u32> {
+        type QueryDmaBufFormatsEXT =
+            unsafe extern "C" fn(*mut c_void, i32, *mut i32, *mut i32) -> u32;
+
+        let Some(query) = self.egl.get_proc_address("eglQueryDmaBufFormatsEXT") else {
+            return Vec::new();
+        };
+
+        unsafe {
+            let query: QueryDmaBufFormatsEXT = std::mem::transmute(query);
+            let display = self.display.as_ptr();
+
+            let mut count = 0;
+            if query(display, 0, std::ptr::null_mut(), &mut count) == 0 || count <= 0 {
+                return Vec::new();
+            }
+            let mut formats = vec![0i32; count as usize];
+            if query(display, count, formats.as_mut_ptr(), &mut count) == 0 {
+                return Vec::new();
+            }
+            formats.truncate(count as usize);
+            formats.into_iter().map(|f| f as u32).collect()
+        }
+    }
+
+    pub fn destroy_surface(&mut self, surface: egl::Surface) {
+        let _ = self.egl.destroy_surface(self.display, surface);
+        // A new surface may get the same handle, and must start with full damage
+        let key = surface.as_ptr() as usize;
+        self.last_layouts.remove(&key);
+        unsafe { self.fbo_pool.release(FboRole::Supersample(key)) };
+    }
+
     pub fn swap_buffers(&self, surface: egl::Surface) -> Result<()> {
         self.egl
             .swap_buffers(self.display, surface)
@@ -126,6 +426,87 @@ impl EglContext {
         Ok(())
     }
 
+    /// Fill a surface with its `background` color and present it, e.g. while
+    /// mirroring is paused
+    pub fn clear(
+        &mut self,
+        surface: egl::Surface,
+        width: i32,
+        height: i32,
+        background: [f32; 4],
+    ) -> Result<()> {
+        self.make_current(surface)?;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, width, height);
+            // Premultiplied, as in draw_to
+            let [r, g, b, a] = background;
+            gl::ClearColor(r * a, g * a, b * a, a);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+        // The next frame has to repaint everything, letterbox bars included
+        self.last_layouts.remove(&(surface.as_ptr() as usize));
+        self.swap_buffers(surface)
+    }
+
+    /// Damage only the drawn region on swap instead of the whole surface.
+    ///
+    /// Returns false if neither EGL_KHR_swap_buffers_with_damage nor the EXT variant exists.
+    pub fn enable_surface_damage(&mut self) -> bool {
+        let extensions = self.query_string(egl::EXTENSIONS);
+        let name = [
+            (
+                "EGL_KHR_swap_buffers_with_damage",
+                "eglSwapBuffersWithDamageKHR",
+            ),
+            (
+                "EGL_EXT_swap_buffers_with_damage",
+                "eglSwapBuffersWithDamageEXT",
+            ),
+        ]
+        .into_iter()
+        .find(|(ext, _)| extensions.split(' ').any(|e| e == *ext))
+        .map(|(_, name)| name);
+
+        self.swap_with_damage =
+            name.and_then(|name| self.egl.get_proc_address(name))
+                .map(|f| unsafe {
+                    std::mem::transmute::<extern "system" fn(), SwapBuffersWithDamage>(f)
+                });
+        self.swap_with_damage.is_some()
+    }
+
+    /// Swap, damaging just `viewport` (GL, bottom-left origin) if it is where we drew last time
+    fn swap_buffers_damaged(
+        &mut self,
+        surface: egl::Surface,
+        viewport: (i32, i32, i32, i32),
+        width: i32,
+        height: i32,
+    ) -> Result<()> {
+        let Some(swap) = self.swap_with_damage else {
+            return self.swap_buffers(surface);
+        };
+
+        // The letterbox bars only need repainting when the layout moved
+        let key = surface.as_ptr() as usize;
+        let (x, y, w, h) = viewport;
+        let layout = [x, y, w, h, width, height];
+        let moved = self.last_layouts.insert(key, layout) != Some(layout);
+        let rect = if moved {
+            [0, 0, width, height]
+        } else {
+            let (x0, y0) = (x.max(0), y.max(0));
+            let (x1, y1) = ((x + w).min(width), (y + h).min(height));
+            [x0, y0, (x1 - x0).max(0), (y1 - y0).max(0)]
+        };
+
+        if unsafe { swap(self.display, surface.as_ptr(), rect.as_ptr(), 1) } != egl::TRUE {
+            bail!("Failed to swap buffers with damage");
+        }
+        Ok(())
+    }
+
     pub fn init_gl(&mut self) -> Result<()> {
         unsafe {
             gl::load_with(|s| {
@@ -135,15 +516,35 @@ impl EglContext {
                     .unwrap_or(std::ptr::null())
             });
 
+            // Resolved once here rather than on every imported frame
+            self.create_image_fn = self
+                .egl
+                .get_proc_address("eglCreateImageKHR")
+                .map(|p| std::mem::transmute::<_, CreateImageKHR>(p));
+            self.destroy_image_fn = self
+                .egl
+                .get_proc_address("eglDestroyImageKHR")
+                .map(|p| std::mem::transmute::<_, DestroyImageKHR>(p));
+            self.image_target_fn = self
+                .egl
+                .get_proc_address("glEGLImageTargetTexture2DOES")
+                .map(|p| std::mem::transmute::<_, ImageTargetTexture2DOES>(p));
+
+            if self.debug && !debug::install_gl_callback(&self.gl_string(gl::EXTENSIONS)) {
+                eprintln!("Warning: GL_KHR_debug not available, only glGetError will be checked");
+            }
+
             // Create shader program
             let vs_src = r#"
                 #version 100
                 attribute vec2 pos;
                 attribute vec2 tex;
+                uniform vec4 u_tex_rect;
+                uniform mat2 u_rotation;
                 varying vec2 v_tex;
                 void main() {
-                    gl_Position = vec4(pos, 0.0, 1.0);
-                    v_tex = tex;
+                    gl_Position = vec4(u_rotation * pos, 0.0, 1.0);
+                    v_tex = u_tex_rect.xy + tex * u_tex_rect.zw;
                 }
             "#;
 
@@ -152,83 +553,187 @@ impl EglContext {
                 precision mediump float;
                 varying vec2 v_tex;
                 uniform sampler2D u_texture;
+                uniform sampler2D u_lut;
+                uniform vec3 u_gain;
+                uniform vec3 u_adjust;
+                uniform mat3 u_filter;
+                uniform vec3 u_filter_offset;
+                uniform vec2 u_lut_coord;
                 void main() {
-                    gl_FragColor = texture2D(u_texture, v_tex);
+                    vec4 color = texture2D(u_texture, v_tex);
+                    vec3 rgb = color.rgb * u_gain;
+                    // Contrast around mid grey, then brightness, then gamma; identity by default
+                    rgb = clamp(((rgb - 0.5) * u_adjust.y + 0.5) * u_adjust.x, 0.0, 1.0);
+                    rgb = pow(rgb, vec3(u_adjust.z));
+                    rgb = clamp(u_filter * rgb + u_filter_offset, 0.0, 1.0);
+                    if (u_lut_coord.x > 0.0) {
+                        vec3 t = clamp(rgb, 0.0, 1.0) * u_lut_coord.x + u_lut_coord.y;
+                        rgb = vec3(
+                            texture2D(u_lut, vec2(t.r, 0.5)).r,
+                            texture2D(u_lut, vec2(t.g, 0.5)).g,
+                            texture2D(u_lut, vec2(t.b, 0.5)).b
+                        );
+                    }
+                    gl_FragColor = vec4(rgb, color.a);
                 }
             "#;
 
-            let vs = self.compile_shader(gl::VERTEX_SHADER, vs_src)?;
-            let fs = self.compile_shader(gl::FRAGMENT_SHADER, fs_src)?;
-
-            self.program = gl::CreateProgram();
-            gl::AttachShader(self.program, vs);
-            gl::AttachShader(self.program, fs);
-            gl::LinkProgram(self.program);
-
-            // Check link status
-            let mut status = 0;
-            gl::GetProgramiv(self.program, gl::LINK_STATUS, &mut status);
-            if status == 0 {
-                bail!("Failed to link shader program");
-            }
-
-            gl::DeleteShader(vs);
-            gl::DeleteShader(fs);
-
-            // Create VAO and VBO
-            let mut vao = 0;
-            gl::GenVertexArrays(1, &mut vao);
-            self.vao = vao;
-            gl::BindVertexArray(vao);
+            self.program = self.link_program(vs_src, fs_src)?;
 
-            // Full screen quad
-            let vertices: [f32; 16] = [
-                // pos      // tex
-                -1.0, -1.0, 0.0, 1.0, 1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0,
-            ];
-
-            let mut vbo = 0;
-            gl::GenBuffers(1, &mut vbo);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (vertices.len() * std::mem::size_of::<f32>()) as isize,
-                vertices.as_ptr() as *const c_void,
-                gl::STATIC_DRAW,
+            // BT.709 limited range YUV to RGB, for formats imported plane by plane
+            let yuv_fs_src = r#"
+                #version 100
+                #ifdef GL_FRAGMENT_PRECISION_HIGH
+                precision highp float;
+                #else
+                precision mediump float;
+                #endif
+                varying vec2 v_tex;
+                uniform sampler2D u_y;
+                uniform sampler2D u_uv;
+                uniform float u_packed_width;
+                void main() {
+                    vec3 yuv;
+                    if (u_packed_width > 0.0) {
+                        // YUYV: each texel is Y0 U Y1 V for a pair of pixels
+                        vec4 texel = texture2D(u_y, v_tex);
+                        bool odd = mod(floor(v_tex.x * u_packed_width), 2.0) >= 1.0;
+                        yuv = vec3(odd ? texel.b : texel.r, texel.g, texel.a);
+                    } else {
+                        yuv = vec3(texture2D(u_y, v_tex).r, texture2D(u_uv, v_tex).rg);
+                    }
+                    float y = (yuv.x - 16.0 / 255.0) * (255.0 / 219.0);
+                    vec2 c = (yuv.yz - 128.0 / 255.0) * (255.0 / 224.0);
+                    gl_FragColor = vec4(
+                        y + 1.5748 * c.y,
+                        y - 0.1873 * c.x - 0.4681 * c.y,
+                        y + 1.8556 * c.x,
+                        1.0
+                    );
+                }
+            "#;
+            self.yuv_program = self.link_program(vs_src, yuv_fs_src)?;
+            gl::UseProgram(self.yuv_program);
+            gl::Uniform1i(gl::GetUniformLocation(self.yuv_program, c"u_y".as_ptr()), 0);
+            gl::Uniform1i(
+                gl::GetUniformLocation(self.yuv_program, c"u_uv".as_ptr()),
+                1,
             );
-
-            let pos_loc = gl::GetAttribLocation(self.program, c"pos".as_ptr());
-            let tex_loc = gl::GetAttribLocation(self.program, c"tex".as_ptr());
-
-            gl::EnableVertexAttribArray(pos_loc as u32);
-            gl::VertexAttribPointer(
-                pos_loc as u32,
-                2,
-                gl::FLOAT,
+            gl::Uniform4f(
+                gl::GetUniformLocation(self.yuv_program, c"u_tex_rect".as_ptr()),
+                0.0,
+                0.0,
+                1.0,
+                1.0,
+            );
+            self.packed_width_location =
+                gl::GetUniformLocation(self.yuv_program, c"u_packed_width".as_ptr());
+            gl::UniformMatrix2fv(
+                gl::GetUniformLocation(self.yuv_program, c"u_rotation".as_ptr()),
+                1,
                 gl::FALSE,
-                (4 * std::mem::size_of::<f32>()) as i32,
-                std::ptr::null(),
+                Transform::Normal.matrix().as_ptr(),
             );
 
-            gl::EnableVertexAttribArray(tex_loc as u32);
-            gl::VertexAttribPointer(
-                tex_loc as u32,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (4 * std::mem::size_of::<f32>()) as i32,
-                (2 * std::mem::size_of::<f32>()) as *const c_void,
+            self.gain_location = gl::GetUniformLocation(self.program, c"u_gain".as_ptr());
+            self.tex_rect_location = gl::GetUniformLocation(self.program, c"u_tex_rect".as_ptr());
+            self.lut_coord_location = gl::GetUniformLocation(self.program, c"u_lut_coord".as_ptr());
+            self.rotation_location = gl::GetUniformLocation(self.program, c"u_rotation".as_ptr());
+            self.adjust_location = gl::GetUniformLocation(self.program, c"u_adjust".as_ptr());
+            self.filter_locations = (
+                gl::GetUniformLocation(self.program, c"u_filter".as_ptr()),
+                gl::GetUniformLocation(self.program, c"u_filter_offset".as_ptr()),
             );
 
-            // Create texture
+            // The frame is sampled from unit 0, the LUT from unit 1
+            gl::UseProgram(self.program);
+            gl::Uniform1i(gl::GetUniformLocation(self.program, c"u_lut".as_ptr()), 1);
+
+            // Full screen quads: dmabuf content is top-down, our FBOs are bottom-up
+            self.vao = self.create_quad(&[
+                // pos      // tex
+                -1.0, -1.0, 0.0, 1.0, 1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0,
+            ]);
+            self.blit_vao = self.create_quad(&[
+                // pos      // tex
+                -1.0, -1.0, 0.0, 0.0, 1.0, -1.0, 1.0, 0.0, -1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0,
+            ]);
+
+            // Create textures
             let mut texture = 0;
             gl::GenTextures(1, &mut texture);
             self.texture = texture;
+            gl::GenTextures(2, self.yuv_textures.as_mut_ptr());
         }
 
         Ok(())
     }
 
+    unsafe fn create_quad(&self, vertices: &[f32; 16]) -> u32 {
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        gl::BindVertexArray(vao);
+
+        let mut vbo = 0;
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            std::mem::size_of_val(vertices) as isize,
+            vertices.as_ptr() as *const c_void,
+            gl::STATIC_DRAW,
+        );
+
+        let pos_loc = gl::GetAttribLocation(self.program, c"pos".as_ptr());
+        let tex_loc = gl::GetAttribLocation(self.program, c"tex".as_ptr());
+
+        gl::EnableVertexAttribArray(pos_loc as u32);
+        gl::VertexAttribPointer(
+            pos_loc as u32,
+            2,
+            gl::FLOAT,
+            gl::FALSE,
+            (4 * std::mem::size_of::<f32>()) as i32,
+            std::ptr::null(),
+        );
+
+        gl::EnableVertexAttribArray(tex_loc as u32);
+        gl::VertexAttribPointer(
+            tex_loc as u32,
+            2,
+            gl::FLOAT,
+            gl::FALSE,
+            (4 * std::mem::size_of::<f32>()) as i32,
+            (2 * std::mem::size_of::<f32>()) as *const c_void,
+        );
+
+        vao
+    }
+
+    /// Compile and link a program; every program shares the quads' attribute locations
+    unsafe fn link_program(&self, vs_src: &str, fs_src: &str) -> Result<u32> {
+        let vs = self.compile_shader(gl::VERTEX_SHADER, vs_src)?;
+        let fs = self.compile_shader(gl::FRAGMENT_SHADER, fs_src)?;
+
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vs);
+        gl::AttachShader(program, fs);
+        gl::BindAttribLocation(program, 0, c"pos".as_ptr());
+        gl::BindAttribLocation(program, 1, c"tex".as_ptr());
+        gl::LinkProgram(program);
+
+        // Check link status
+        let mut status = 0;
+        gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+        if status == 0 {
+            bail!("Failed to link shader program");
+        }
+
+        gl::DeleteShader(vs);
+        gl::DeleteShader(fs);
+        Ok(program)
+    }
+
     unsafe fn compile_shader(&self, shader_type: u32, source: &str) -> Result<u32> {
         let shader = gl::CreateShader(shader_type);
         let source_ptr = source.as_ptr() as *const i8;
@@ -255,161 +760,624 @@ impl EglContext {
         Ok(shader)
     }
 
-    pub fn render_frame(
-        &self,
-        frame: &CapturedFrame,
+    /// Import the frame's dmabuf and get a top-down RGB texture of it.
+    ///
+    /// NV12 and YUYV are imported plane by plane and converted to RGB in a shader;
+    /// everything else is a single EGL image bound to `self.texture`.
+    unsafe fn import_frame(&mut self, frame: &CapturedFrame) -> Result<ImportedFrame> {
+        let (width, height) = (frame.width, frame.height);
+        if frame.format == DrmFourcc::Nv12 as u32 && frame.planes.len() >= 2 {
+            let y = self.create_image(width, height, DrmFourcc::R8 as u32, &frame.planes[..1])?;
+            let uv = self.create_image(
+                width.div_ceil(2),
+                height.div_ceil(2),
+                DrmFourcc::Gr88 as u32,
+                &frame.planes[1..2],
+            )?;
+            self.bind_image(self.yuv_textures[0], y, gl::LINEAR)?;
+            self.bind_image(self.yuv_textures[1], uv, gl::LINEAR)?;
+            let texture = self.convert_yuv(frame, 0.0);
+            return Ok(ImportedFrame { texture });
+        }
+        if frame.format == DrmFourcc::Yuyv as u32 {
+            // Pairs of pixels as one RGBA texel; nearest sampling keeps them apart
+            let packed = self.create_image(
+                width.div_ceil(2),
+                height,
+                DrmFourcc::Abgr8888 as u32,
+                &frame.planes[..1],
+            )?;
+            self.bind_image(self.yuv_textures[0], packed, gl::NEAREST)?;
+            let texture = self.convert_yuv(frame, width as f32);
+            return Ok(ImportedFrame { texture });
+        }
+
+        let image = self.create_image(width, height, frame.format, &frame.planes)?;
+        self.bind_image(self.texture, image, gl::LINEAR)?;
+        Ok(ImportedFrame {
+            texture: self.texture,
+        })
+    }
+
+    /// Get an EGL image of up to three dmabuf planes, reusing the cached one when
+    /// the same buffer was imported before
+    unsafe fn create_image(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: u32,
+        planes: &[DmabufPlane],
+    ) -> Result<*mut c_void> {
+        if planes.is_empty() || planes.len() > EGL_DMA_BUF_PLANE_ATTRIBS.len() {
+            bail!("Can't import a dmabuf with {} planes", planes.len());
+        }
+
+        let mut key = ImageKey {
+            width,
+            height,
+            format,
+            planes: Vec::with_capacity(planes.len()),
+        };
+        for plane in planes {
+            let stat = nix::sys::stat::fstat(plane.fd).context("Failed to stat dmabuf fd")?;
+            key.planes.push((
+                stat.st_dev as u64,
+                stat.st_ino as u64,
+                plane.offset,
+                plane.stride,
+                plane.modifier,
+            ));
+        }
+        if let Some(index) = self.image_cache.iter().position(|(k, _)| *k == key) {
+            let entry = self.image_cache.remove(index);
+            let image = entry.1;
+            self.image_cache.push(entry);
+            return Ok(image);
+        }
+
+        let mut attribs = vec![
+            EGL_WIDTH,
+            width as i32,
+            EGL_HEIGHT,
+            height as i32,
+            EGL_LINUX_DRM_FOURCC_EXT,
+            format as i32,
+        ];
+        for (plane, names) in planes.iter().zip(EGL_DMA_BUF_PLANE_ATTRIBS) {
+            attribs.extend([
+                names[0],
+                plane.fd,
+                names[1],
+                plane.offset as i32,
+                names[2],
+                plane.stride as i32,
+            ]);
+            // Without the modifier a tiled or compressed buffer is read as linear
+            if plane.modifier != DRM_FORMAT_MOD_INVALID && self.import_modifiers {
+                attribs.extend([
+                    names[3],
+                    plane.modifier as u32 as i32,
+                    names[4],
+                    (plane.modifier >> 32) as u32 as i32,
+                ]);
+            }
+        }
+        attribs.push(egl::NONE);
+
+        let create_image = self
+            .create_image_fn
+            .ok_or_else(|| anyhow::anyhow!("eglCreateImageKHR not found"))?;
+        let image = create_image(
+            self.display,
+            EGL_NO_CONTEXT,
+            EGL_LINUX_DMA_BUF_EXT,
+            std::ptr::null_mut(),
+            attribs.as_ptr(),
+        );
+
+        if image.is_null() {
+            bail!("Failed to create EGL image from dmabuf");
+        }
+
+        if self.image_cache.len() >= IMAGE_CACHE_SIZE {
+            let (_, oldest) = self.image_cache.remove(0);
+            self.destroy_image(oldest);
+        }
+        self.image_cache.push((key, image));
+        Ok(image)
+    }
+
+    /// Back a texture with an EGL image
+    unsafe fn bind_image(&self, texture: u32, image: *mut c_void, filter: u32) -> Result<()> {
+        let image_target = self
+            .image_target_fn
+            .ok_or_else(|| anyhow::anyhow!("glEGLImageTargetTexture2DOES not found"))?;
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        image_target(gl::TEXTURE_2D, image);
+
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        Ok(())
+    }
+
+    /// Draw the bound YUV plane textures into a source-sized RGB target.
+    ///
+    /// The quad is flipped so the result is top-down like an imported frame.
+    /// `packed_width` is the frame width for YUYV, or zero for NV12.
+    unsafe fn convert_yuv(&mut self, frame: &CapturedFrame, packed_width: f32) -> u32 {
+        let fbo = self
+            .fbo_pool
+            .get(FboRole::Convert, frame.width as i32, frame.height as i32);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.framebuffer);
+        gl::Viewport(0, 0, fbo.width, fbo.height);
+        gl::UseProgram(self.yuv_program);
+        gl::Uniform1f(self.packed_width_location, packed_width);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, self.yuv_textures[1]);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.yuv_textures[0]);
+        gl::BindVertexArray(self.blit_vao);
+        gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        if self.debug {
+            debug::check_gl_errors("YUV conversion");
+        }
+        fbo.texture
+    }
+
+    unsafe fn destroy_image(&self, image: *mut c_void) {
+        if let Some(destroy_image) = self.destroy_image_fn {
+            destroy_image(self.display, image);
+        }
+    }
+
+    /// Destroy every cached EGL image, releasing the dmabufs they hold on to
+    fn clear_image_cache(&mut self) {
+        for (_, image) in std::mem::take(&mut self.image_cache) {
+            unsafe { self.destroy_image(image) };
+        }
+    }
+
+    /// Bind the shader program with the RGB gain, the sampled texture rectangle, and
+    /// optionally a calibration LUT (texture, size)
+    unsafe fn use_program(&self, gain: [f32; 3], tex_rect: [f32; 4], lut: Option<(u32, usize)>) {
+        gl::UseProgram(self.program);
+        gl::Uniform3f(self.gain_location, gain[0], gain[1], gain[2]);
+        gl::UniformMatrix2fv(
+            self.rotation_location,
+            1,
+            gl::FALSE,
+            Transform::Normal.matrix().as_ptr(),
+        );
+        gl::Uniform3f(self.adjust_location, 1.0, 1.0, 1.0);
+        self.set_filter(ColorFilter::None);
+        match lut {
+            Some((texture, size)) => {
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+                gl::ActiveTexture(gl::TEXTURE0);
+                // Map 0..1 onto the centers of the first and last texels
+                let n = size as f32;
+                gl::Uniform2f(self.lut_coord_location, (n - 1.0) / n, 0.5 / n);
+            }
+            None => gl::Uniform2f(self.lut_coord_location, 0.0, 0.0),
+        }
+        gl::Uniform4f(
+            self.tex_rect_location,
+            tex_rect[0],
+            tex_rect[1],
+            tex_rect[2],
+            tex_rect[3],
+        );
+    }
+
+    /// Set the color filter uniforms of the bound program
+    unsafe fn set_filter(&self, filter: ColorFilter) {
+        let (matrix, offset) = filter.affine();
+        // The matrix is row-major, so GL has to transpose it; GLES2 can't, so do it here
+        let columns = [
+            matrix[0], matrix[3], matrix[6], matrix[1], matrix[4], matrix[7], matrix[2], matrix[5],
+            matrix[8],
+        ];
+        gl::UniformMatrix3fv(self.filter_locations.0, 1, gl::FALSE, columns.as_ptr());
+        gl::Uniform3f(self.filter_locations.1, offset[0], offset[1], offset[2]);
+    }
+
+    /// The target's (or else the context's) source region clamped to the frame, or the whole frame
+    fn clamped_region(&self, size: (u32, u32), params: &TargetParams) -> (u32, u32, u32, u32) {
+        let (width, height) = size;
+        match params.source_region.or(self.source_region) {
+            Some((x, y, w, h)) => {
+                let x = x.min(width.saturating_sub(1));
+                let y = y.min(height.saturating_sub(1));
+                (x, y, w.min(width - x), h.min(height - y))
+            }
+            None => (0, 0, width, height),
+        }
+    }
+
+    /// Texcoord offset/scale selecting `region`; FBO textures are stored bottom-up
+    fn region_tex_rect(
+        size: (u32, u32),
+        region: (u32, u32, u32, u32),
+        bottom_up: bool,
+    ) -> [f32; 4] {
+        let (x, y, w, h) = region;
+        let (fw, fh) = (size.0 as f32, size.1 as f32);
+        let top = if bottom_up {
+            1.0 - (y + h) as f32 / fh
+        } else {
+            y as f32 / fh
+        };
+        [x as f32 / fw, top, w as f32 / fw, h as f32 / fh]
+    }
+
+    /// Copy the imported frame into a regular texture and build its mipmap chain.
+    ///
+    /// EGLImage-backed textures can't have mipmaps generated, so this goes via an FBO.
+    /// Returns the mipmapped texture, which is bottom-up like all our FBOs.
+    unsafe fn build_mipmaps(&mut self, frame: &CapturedFrame, texture: u32) -> u32 {
+        let fbo = self
+            .fbo_pool
+            .get(FboRole::Mipmap, frame.width as i32, frame.height as i32);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.framebuffer);
+        gl::Viewport(0, 0, fbo.width, fbo.height);
+        self.use_program(IDENTITY_GAIN, FULL_TEX_RECT, None);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::BindVertexArray(self.vao);
+        gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+
+        gl::BindTexture(gl::TEXTURE_2D, fbo.texture);
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_MIN_FILTER,
+            gl::LINEAR_MIPMAP_LINEAR as i32,
+        );
+        fbo.texture
+    }
+
+    /// Import a frame to check the driver accepts it
+    pub fn verify_import(&mut self, frame: &CapturedFrame) -> Result<()> {
+        self.make_current_surfaceless()?;
+        unsafe { self.import_frame(frame) }.map(|_| ())
+    }
+
+    /// Blend the frame into the motion blur accumulator.
+    ///
+    /// Each frame gets weight 1/k, so the accumulator is a running average over
+    /// roughly the last k frames; until k frames have arrived, all are weighted equally.
+    pub fn accumulate(&mut self, frame: &CapturedFrame) -> Result<()> {
+        if frame.planes.is_empty() {
+            return Ok(());
+        }
+        self.make_current_surfaceless()?;
+        let size = (frame.width as i32, frame.height as i32);
+
+        unsafe {
+            let imported = self.import_frame(frame)?;
+            let fbo = self.fbo_pool.get(FboRole::Accumulate, size.0, size.1);
+            // A new source size starts a fresh average
+            if size != self.blur_size {
+                self.blur_size = size;
+                self.blur_frames = 0;
+            }
+            self.blur_frames = (self.blur_frames + 1).min(self.motion_blur);
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.framebuffer);
+            gl::Viewport(0, 0, size.0, size.1);
+            gl::Enable(gl::BLEND);
+            gl::BlendColor(0.0, 0.0, 0.0, 1.0 / self.blur_frames as f32);
+            gl::BlendFunc(gl::CONSTANT_ALPHA, gl::ONE_MINUS_CONSTANT_ALPHA);
+            self.use_program(IDENTITY_GAIN, FULL_TEX_RECT, None);
+            gl::BindTexture(gl::TEXTURE_2D, imported.texture);
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+            gl::Disable(gl::BLEND);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            if self.debug {
+                debug::check_gl_errors("motion blur accumulation");
+            }
+        }
+        Ok(())
+    }
+
+    /// The motion blur accumulator's texture, if it holds frames of this frame's size
+    unsafe fn blurred_texture(&mut self, frame: &CapturedFrame) -> Option<u32> {
+        let size = (frame.width as i32, frame.height as i32);
+        if self.motion_blur <= 1 || self.blur_frames == 0 || size != self.blur_size {
+            return None;
+        }
+        Some(
+            self.fbo_pool
+                .get(FboRole::Accumulate, size.0, size.1)
+                .texture,
+        )
+    }
+
+    /// The LUT as a size x 1 texture, uploaded on first use
+    unsafe fn lut_texture(&mut self, lut: &Lut1d) -> u32 {
+        let key = lut as *const Lut1d as usize;
+        if let Some(&texture) = self.lut_textures.get(&key) {
+            return texture;
+        }
+
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        let texels = lut.texels();
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA as i32,
+            lut.entries.len() as i32,
+            1,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            texels.as_ptr() as *const c_void,
+        );
+        // Linear filtering interpolates between entries
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        if self.debug {
+            debug::check_gl_errors("LUT upload");
+        }
+        self.lut_textures.insert(key, texture);
+        texture
+    }
+
+    /// Draw the frame into an offscreen target and read it back as top-down RGBA8888
+    pub fn read_back(&mut self, frame: &CapturedFrame) -> Result<Vec<u8>> {
+        self.make_current_surfaceless()?;
+        let (width, height) = (frame.width as i32, frame.height as i32);
+
+        unsafe {
+            // With motion blur, read the blended frame straight from the accumulator
+            if self.blurred_texture(frame).is_some() {
+                let fbo = self.fbo_pool.get(FboRole::Accumulate, width, height);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.framebuffer);
+            } else {
+                let imported = self.import_frame(frame)?;
+                let fbo = self.fbo_pool.get(FboRole::Readback, width, height);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.framebuffer);
+                gl::Viewport(0, 0, width, height);
+                self.use_program(IDENTITY_GAIN, FULL_TEX_RECT, None);
+                gl::BindTexture(gl::TEXTURE_2D, imported.texture);
+                gl::BindVertexArray(self.vao);
+                gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+            }
+            let pixels = read_pixels(width, height);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            Ok(pixels)
+        }
+    }
+
+    /// Import the frame once for drawing onto every target with `draw_to`
+    pub fn bind_frame(&mut self, frame: &CapturedFrame) -> Result<()> {
+        self.make_current_surfaceless()?;
+        let mut bound = BoundFrame {
+            width: frame.width,
+            height: frame.height,
+            source: None,
+            bottom_up: false,
+            mipmapped: false,
+        };
+
+        unsafe {
+            // With motion blur, the accumulator already holds this frame
+            if let Some(texture) = self.blurred_texture(frame) {
+                bound.source = Some((texture, self.blit_vao));
+                bound.bottom_up = true;
+            } else if !frame.planes.is_empty() {
+                let imported = self.import_frame(frame)?;
+                if self.debug {
+                    debug::check_gl_errors("dmabuf import");
+                }
+                if self.mipmap {
+                    let texture = self.build_mipmaps(frame, imported.texture);
+                    if self.debug {
+                        debug::check_gl_errors("mipmap generation");
+                    }
+                    bound.source = Some((texture, self.blit_vao));
+                    bound.bottom_up = true;
+                    bound.mipmapped = true;
+                } else {
+                    bound.source = Some((imported.texture, self.vao));
+                }
+            }
+        }
+
+        self.bound = Some(bound);
+        Ok(())
+    }
+
+    /// Draw the frame set up by `bind_frame` onto a target surface and swap it.
+    /// With `read_back`, also returns what was drawn as top-down RGBA8888.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_to(
+        &mut self,
         surface: egl::Surface,
         width: i32,
         height: i32,
         scale_mode: ScaleMode,
-    ) -> Result<()> {
+        params: &TargetParams,
+        stats: Option<&str>,
+        read_back: bool,
+    ) -> Result<Option<Vec<u8>>> {
+        let bound = self
+            .bound
+            .ok_or_else(|| anyhow::anyhow!("No frame bound for drawing"))?;
         self.make_current(surface)?;
 
-        unsafe {
-            let src_w = frame.width as f32;
-            let src_h = frame.height as f32;
-            let dst_w = width as f32;
-            let dst_h = height as f32;
-            let src_aspect = src_w / src_h;
-            let dst_aspect = dst_w / dst_h;
-
-            let (vp_x, vp_y, vp_w, vp_h) = match scale_mode {
-                ScaleMode::Stretch => {
-                    // Fill entire target, ignore aspect ratio
-                    (0, 0, width, height)
-                }
-                ScaleMode::Fit => {
-                    // Preserve aspect ratio, fit within target (letterbox/pillarbox)
-                    if src_aspect > dst_aspect {
-                        // Source is wider - letterbox (black bars top/bottom)
-                        let vp_h = (dst_w / src_aspect) as i32;
-                        let vp_y = (height - vp_h) / 2;
-                        (0, vp_y, width, vp_h)
-                    } else {
-                        // Source is taller - pillarbox (black bars left/right)
-                        let vp_w = (dst_h * src_aspect) as i32;
-                        let vp_x = (width - vp_w) / 2;
-                        (vp_x, 0, vp_w, height)
-                    }
-                }
-                ScaleMode::Fill => {
-                    // Preserve aspect ratio, fill target completely (crops edges)
-                    if src_aspect > dst_aspect {
-                        // Source is wider - extend beyond left/right edges
-                        let vp_w = (dst_h * src_aspect) as i32;
-                        let vp_x = (width - vp_w) / 2;
-                        (vp_x, 0, vp_w, height)
-                    } else {
-                        // Source is taller - extend beyond top/bottom edges
-                        let vp_h = (dst_w / src_aspect) as i32;
-                        let vp_y = (height - vp_h) / 2;
-                        (0, vp_y, width, vp_h)
+        let damage = unsafe {
+            let size = (bound.width, bound.height);
+            let region = self.clamped_region(size, params);
+            let (layout_w, layout_h) =
+                layout_size(region.2, region.3, self.source_scale, params.transform);
+            // The mode this target is drawn in, for the default filtering
+            let drawn_mode = resolve_mode(
+                params.scale_mode.unwrap_or(scale_mode),
+                layout_w,
+                layout_h,
+                width,
+                height,
+            );
+            // A target with its own scale mode isn't animated with the others
+            let (mut vp_x, mut vp_y, mut vp_w, mut vp_h) =
+                match (&mut self.transition, params.scale_mode) {
+                    (Some(transition), None) => {
+                        transition.set_mode(scale_mode);
+                        transition.viewport(self.crop_anchor, layout_w, layout_h, width, height)
                     }
-                }
-                ScaleMode::Center => {
-                    // Display at 1:1 pixel ratio, centered (no scaling)
-                    let vp_w = frame.width as i32;
-                    let vp_h = frame.height as i32;
-                    let vp_x = (width - vp_w) / 2;
-                    let vp_y = (height - vp_h) / 2;
-                    (vp_x, vp_y, vp_w, vp_h)
-                }
+                    (_, own_mode) => compute_viewport(
+                        own_mode.unwrap_or(scale_mode),
+                        self.crop_anchor,
+                        layout_w,
+                        layout_h,
+                        width,
+                        height,
+                    ),
+                };
+
+            // Damage is reported in surface pixels, before any supersampling
+            let damage = (vp_x, vp_y, vp_w, vp_h);
+
+            // When supersampling, draw into a larger offscreen target first
+            let supersample = if self.render_scale > 1.0 {
+                let factor = self.render_scale;
+                let fbo = self.fbo_pool.get(
+                    FboRole::Supersample(surface.as_ptr() as usize),
+                    (width as f32 * factor) as i32,
+                    (height as f32 * factor) as i32,
+                );
+                vp_x = (vp_x as f32 * factor) as i32;
+                vp_y = (vp_y as f32 * factor) as i32;
+                vp_w = (vp_w as f32 * factor) as i32;
+                vp_h = (vp_h as f32 * factor) as i32;
+                Some(fbo)
+            } else {
+                None
             };
+            let (target_w, target_h) = supersample.map_or((width, height), |f| (f.width, f.height));
+            gl::BindFramebuffer(gl::FRAMEBUFFER, supersample.map_or(0, |f| f.framebuffer));
 
-            // Clear entire surface to black first
-            gl::Viewport(0, 0, width, height);
-            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            // Clear entire surface to the background first; Wayland expects premultiplied alpha
+            let [r, g, b, a] = params.background;
+            gl::Viewport(0, 0, target_w, target_h);
+            gl::ClearColor(r * a, g * a, b * a, a);
             gl::Clear(gl::COLOR_BUFFER_BIT);
 
             // Set viewport for rendering
             gl::Viewport(vp_x, vp_y, vp_w, vp_h);
 
-            // Import dmabuf as EGL image and bind to texture
-            if !frame.planes.is_empty() {
-                let plane = &frame.planes[0];
-
-                let attribs: [i32; 13] = [
-                    EGL_WIDTH,
-                    frame.width as i32,
-                    EGL_HEIGHT,
-                    frame.height as i32,
-                    EGL_LINUX_DRM_FOURCC_EXT,
-                    frame.format as i32,
-                    EGL_DMA_BUF_PLANE0_FD_EXT,
-                    plane.fd,
-                    EGL_DMA_BUF_PLANE0_OFFSET_EXT,
-                    plane.offset as i32,
-                    EGL_DMA_BUF_PLANE0_PITCH_EXT,
-                    plane.stride as i32,
-                    egl::NONE,
-                ];
-
-                // Use eglCreateImageKHR
-                #[allow(improper_ctypes_definitions)]
-                type CreateImageKHR = unsafe extern "C" fn(
-                    egl::Display,
-                    *mut c_void, // EGLContext as raw pointer
-                    u32,
-                    *mut c_void,
-                    *const i32,
-                ) -> *mut c_void;
-
-                let create_image: CreateImageKHR = std::mem::transmute(
-                    self.egl
-                        .get_proc_address("eglCreateImageKHR")
-                        .ok_or_else(|| anyhow::anyhow!("eglCreateImageKHR not found"))?,
+            if let Some((source_texture, source_vao)) = bound.source {
+                // Render
+                let lut = params
+                    .lut
+                    .as_ref()
+                    .map(|lut| (self.lut_texture(lut), lut.entries.len()));
+                let tex_rect = Self::region_tex_rect(size, region, bound.bottom_up);
+                self.use_program(params.color_gain, tex_rect, lut);
+                gl::UniformMatrix2fv(
+                    self.rotation_location,
+                    1,
+                    gl::FALSE,
+                    params.transform.matrix().as_ptr(),
                 );
-
-                let image = create_image(
-                    self.display,
-                    EGL_NO_CONTEXT,
-                    EGL_LINUX_DMA_BUF_EXT,
-                    std::ptr::null_mut(),
-                    attribs.as_ptr(),
+                gl::Uniform3f(
+                    self.adjust_location,
+                    self.brightness,
+                    self.contrast,
+                    1.0 / self.gamma,
                 );
-
-                if image.is_null() {
-                    bail!("Failed to create EGL image from dmabuf");
+                self.set_filter(self.filter);
+                gl::BindTexture(gl::TEXTURE_2D, source_texture);
+                // Center maps pixels 1:1, where interpolation would only blur them
+                let nearest = self
+                    .filter_mode
+                    .unwrap_or(if drawn_mode == ScaleMode::Center {
+                        FilterMode::Nearest
+                    } else {
+                        FilterMode::Linear
+                    })
+                    == FilterMode::Nearest;
+                if nearest {
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
                 }
-
-                // Bind to texture
-                gl::BindTexture(gl::TEXTURE_2D, self.texture);
-
-                type ImageTargetTexture2DOES = unsafe extern "C" fn(u32, *mut c_void);
-                let image_target: ImageTargetTexture2DOES = std::mem::transmute(
-                    self.egl
-                        .get_proc_address("glEGLImageTargetTexture2DOES")
-                        .ok_or_else(|| anyhow::anyhow!("glEGLImageTargetTexture2DOES not found"))?,
-                );
-                image_target(gl::TEXTURE_2D, image);
-
-                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-
-                // Render
-                gl::UseProgram(self.program);
-                gl::BindVertexArray(self.vao);
+                gl::BindVertexArray(source_vao);
+                gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+                if nearest {
+                    // Restore the filtering the next target and the other passes expect
+                    let min_filter = if bound.mipmapped {
+                        gl::LINEAR_MIPMAP_LINEAR
+                    } else {
+                        gl::LINEAR
+                    };
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+                }
+                if self.debug {
+                    debug::check_gl_errors("drawing the frame");
+                }
+            }
+            // Downsample the offscreen target onto the surface
+            if let Some(fbo) = supersample {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                gl::Viewport(0, 0, width, height);
+                self.use_program(IDENTITY_GAIN, FULL_TEX_RECT, None);
+                gl::BindTexture(gl::TEXTURE_2D, fbo.texture);
+                gl::BindVertexArray(self.blit_vao);
                 gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+                if self.debug {
+                    debug::check_gl_errors("supersample downscale");
+                }
+            }
 
-                // Destroy EGL image
-                #[allow(improper_ctypes_definitions)]
-                type DestroyImageKHR = unsafe extern "C" fn(egl::Display, *mut c_void) -> u32;
-                let destroy_image: DestroyImageKHR = std::mem::transmute(
-                    self.egl
-                        .get_proc_address("eglDestroyImageKHR")
-                        .ok_or_else(|| anyhow::anyhow!("eglDestroyImageKHR not found"))?,
-                );
-                destroy_image(self.display, image);
+            // The readout sits in a corner, possibly outside the image, so damage everything
+            if let Some(text) = stats {
+                stats::draw_text(text, height);
+                (0, 0, width, height)
+            } else {
+                damage
             }
-        }
+        };
 
-        self.swap_buffers(surface)?;
-        Ok(())
+        // What the target shows, read before the swap leaves the back buffer undefined
+        let pixels = read_back.then(|| unsafe { read_pixels(width, height) });
+        self.swap_buffers_damaged(surface, damage, width, height)?;
+        Ok(pixels)
     }
 }
 
+/// Read the bound framebuffer as top-down RGBA8888
+///
+/// # Safety
+/// A GL context must be current with the framebuffer to read bound.
+unsafe fn read_pixels(width: i32, height: i32) -> Vec<u8> {
+    let row = width as usize * 4;
+    let mut pixels = vec![0u8; row * height as usize];
+    gl::ReadPixels(
+        0,
+        0,
+        width,
+        height,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as *mut c_void,
+    );
+    // GL reads rows bottom-up
+    pixels.chunks_exact(row).rev().flatten().copied().collect()
+}
+
 impl Drop for EglContext {
     fn drop(&mut self) {
+        self.clear_image_cache();
         let _ = self.egl.destroy_context(self.display, self.context);
         let _ = self.egl.terminate(self.display);
     }
@@ -0,0 +1,112 @@
+/// What an offscreen target is used for; targets with different roles are never shared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FboRole {
+    /// Target-sized (times --render-scale) buffer that is downsampled onto the
+    /// surface, one per target, keyed by its EGL surface handle
+    Supersample(usize),
+    /// Source-sized copy of the frame that mipmaps are generated from
+    Mipmap,
+    /// Source-sized copy of the frame read back to the CPU
+    Readback,
+    /// Source-sized RGB conversion of a YUV frame
+    Convert,
+    /// Source-sized running average of recent frames (--motion-blur)
+    Accumulate,
+}
+
+/// Offscreen render target backed by a texture
+#[derive(Debug, Clone, Copy)]
+pub struct Fbo {
+    pub role: FboRole,
+    pub framebuffer: u32,
+    pub texture: u32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Offscreen targets kept alive across frames, one per role. A role requested
+/// at a new size gets its FBO replaced, so resizes don't pile up GPU memory.
+///
+/// The GL objects belong to the context that was current when they were
+/// created, so the pool must be discarded when the context is recreated.
+pub struct FboPool {
+    fbos: Vec<Fbo>,
+}
+
+impl FboPool {
+    pub fn new() -> Self {
+        Self { fbos: Vec::new() }
+    }
+
+    /// Get an FBO of the given role and size, creating it on first use and
+    /// deleting the role's FBO of another size.
+    ///
+    /// # Safety
+    /// A GL context must be current.
+    pub unsafe fn get(&mut self, role: FboRole, width: i32, height: i32) -> Fbo {
+        if let Some(fbo) = self.fbos.iter().find(|f| f.role == role) {
+            if fbo.width == width && fbo.height == height {
+                return *fbo;
+            }
+            self.release(role);
+        }
+
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA as i32,
+            width,
+            height,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            std::ptr::null(),
+        );
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::FramebufferTexture2D(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            texture,
+            0,
+        );
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        let fbo = Fbo {
+            role,
+            framebuffer,
+            texture,
+            width,
+            height,
+        };
+        self.fbos.push(fbo);
+        fbo
+    }
+
+    /// Delete the FBO of a role, if there is one, e.g. when its target is destroyed
+    ///
+    /// # Safety
+    /// The GL context the FBO was created in must be current.
+    pub unsafe fn release(&mut self, role: FboRole) {
+        if let Some(index) = self.fbos.iter().position(|f| f.role == role) {
+            let fbo = self.fbos.swap_remove(index);
+            gl::DeleteFramebuffers(1, &fbo.framebuffer);
+            gl::DeleteTextures(1, &fbo.texture);
+        }
+    }
+
+    /// Forget all FBOs without deleting them (their context is already gone)
+    pub fn reset(&mut self) {
+        self.fbos.clear();
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Largest LUT we accept; GLES only guarantees textures this wide on common hardware
+pub const MAX_LUT_SIZE: usize = 4096;
+
+/// Per-channel 1D lookup table, e.g. a measured display calibration
+#[derive(Debug)]
+pub struct Lut1d {
+    /// Output RGB for evenly spaced inputs from 0.0 to 1.0
+    pub entries: Vec<[f32; 3]>,
+}
+
+impl Lut1d {
+    /// Load a 1D LUT in the `.cube` format.
+    ///
+    /// Supports `LUT_1D_SIZE`, `TITLE`, comments, and the default domain of 0..1;
+    /// 3D LUTs and custom domains are rejected.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read LUT {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid LUT {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut size = None;
+        let mut entries = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => {
+                    let n: usize = words
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .with_context(|| format!("line {}: invalid LUT_1D_SIZE", number + 1))?;
+                    if !(2..=MAX_LUT_SIZE).contains(&n) {
+                        bail!("LUT_1D_SIZE must be between 2 and {}", MAX_LUT_SIZE);
+                    }
+                    size = Some(n);
+                }
+                "LUT_3D_SIZE" => bail!("3D LUTs are not supported, only LUT_1D_SIZE"),
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let default = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    let values: Vec<f32> = words.filter_map(|v| v.parse().ok()).collect();
+                    if values.len() != 3 || values.iter().any(|&v| v != default) {
+                        bail!(
+                            "line {}: only the default {} is supported",
+                            number + 1,
+                            keyword
+                        );
+                    }
+                }
+                _ => {
+                    let values: Vec<f32> = line
+                        .split_whitespace()
+                        .map(|v| v.parse())
+                        .collect::<Result<_, _>>()
+                        .with_context(|| format!("line {}: unexpected '{}'", number + 1, line))?;
+                    let [r, g, b] = values[..] else {
+                        bail!(
+                            "line {}: expected 3 values, got {}",
+                            number + 1,
+                            values.len()
+                        );
+                    };
+                    entries.push([r, g, b]);
+                }
+            }
+        }
+
+        let Some(size) = size else {
+            bail!("missing LUT_1D_SIZE");
+        };
+        if entries.len() != size {
+            bail!(
+                "LUT_1D_SIZE is {} but {} entries follow",
+                size,
+                entries.len()
+            );
+        }
+        Ok(Self { entries })
+    }
+
+    /// The table as RGBA8 texels, for uploading as a size x 1 texture
+    pub fn texels(&self) -> Vec<u8> {
+        let to_byte = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        self.entries
+            .iter()
+            .flat_map(|&[r, g, b]| [to_byte(r), to_byte(g), to_byte(b), 255])
+            .collect()
+    }
+}
@@ -1,10 +1,29 @@
+pub mod color;
+pub mod debug;
 pub mod egl;
+pub mod fbo;
+pub mod lut;
+pub mod stats;
 pub mod surface;
+pub mod viewport;
 
+pub use color::{temperature_gain, ColorFilter, NEUTRAL_TEMPERATURE};
 pub use egl::EglContext;
+pub use lut::Lut1d;
+pub use stats::FrameStats;
 pub use surface::MirrorSurface;
+pub use viewport::{compute_viewport, layout_size, resolve_mode, ViewportTransition};
 
-#[derive(Debug, Clone, Copy, Default)]
+use std::sync::Arc;
+use wayland_client::protocol::wl_output;
+
+/// How the source is laid out on a target.
+///
+/// All modes except Center only depend on the source aspect ratio, so the units of
+/// the source size don't matter. Center maps source pixels 1:1 onto target buffer
+/// pixels: by default those are the captured device pixels, or the source's
+/// logical pixels when the capture is reconciled with the source output scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum ScaleMode {
     /// Preserve aspect ratio, fit within target (letterbox/pillarbox)
     #[default]
@@ -15,4 +34,257 @@ pub enum ScaleMode {
     Stretch,
     /// Display at 1:1 pixel ratio, centered (no scaling)
     Center,
+    /// Match the target width, scaling height proportionally (may crop or letterbox)
+    FitWidth,
+    /// Match the target height, scaling width proportionally (may crop or pillarbox)
+    FitHeight,
+    /// Center when the source fits within the target, otherwise Fit (decided per frame)
+    Auto,
+}
+
+/// How the source texture is sampled when drawn onto a target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Smooth interpolation; blurs when pixels map 1:1 or are magnified
+    Linear,
+    /// Sharp pixels; aliases when the source is scaled down
+    Nearest,
+}
+
+/// Rendering parameters that differ between targets
+#[derive(Debug, Clone)]
+pub struct TargetParams {
+    /// RGB gain applied when drawing, e.g. for color temperature correction
+    pub color_gain: [f32; 3],
+    /// Part of the frame (x, y, width, height in buffer pixels) this target shows,
+    /// overriding the context-wide source region
+    pub source_region: Option<(u32, u32, u32, u32)>,
+    /// Calibration LUT applied after the gain
+    pub lut: Option<Arc<Lut1d>>,
+    /// Rotation of the image on this target
+    pub transform: Transform,
+    /// Straight (not premultiplied) RGBA the letterbox bars are cleared to
+    pub background: [f32; 4],
+    /// Scale mode on this target, overriding the renderer's
+    pub scale_mode: Option<ScaleMode>,
+}
+
+impl Default for TargetParams {
+    fn default() -> Self {
+        Self {
+            color_gain: [1.0, 1.0, 1.0],
+            source_region: None,
+            lut: None,
+            transform: Transform::Normal,
+            background: [0.0, 0.0, 0.0, 1.0],
+            scale_mode: None,
+        }
+    }
+}
+
+/// Clockwise rotation of the mirrored image, optionally after flipping it
+/// horizontally, e.g. for a physically rotated target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transform {
+    #[default]
+    Normal,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+impl Transform {
+    const ALL: [Transform; 8] = [
+        Transform::Normal,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    /// The transform that turns an output's captured buffer upright again.
+    ///
+    /// Buffers are exported in the output's device orientation, which the compositor
+    /// reached by applying the output transform (counter-clockwise in wl_output terms).
+    pub fn undoing(transform: wl_output::Transform) -> Self {
+        match transform {
+            wl_output::Transform::_90 => Transform::Rotate90,
+            wl_output::Transform::_180 => Transform::Rotate180,
+            wl_output::Transform::_270 => Transform::Rotate270,
+            // Flipped transforms are their own inverse
+            wl_output::Transform::Flipped => Transform::Flipped,
+            wl_output::Transform::Flipped90 => Transform::Flipped270,
+            wl_output::Transform::Flipped180 => Transform::Flipped180,
+            wl_output::Transform::Flipped270 => Transform::Flipped90,
+            _ => Transform::Normal,
+        }
+    }
+
+    /// Whether the image's width and height trade places on the target
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Transform::Rotate90
+                | Transform::Rotate270
+                | Transform::Flipped90
+                | Transform::Flipped270
+        )
+    }
+
+    /// Column-major 2x2 matrix transforming clip-space positions
+    pub fn matrix(self) -> [f32; 4] {
+        match self {
+            Transform::Normal => [1.0, 0.0, 0.0, 1.0],
+            Transform::Rotate90 => [0.0, -1.0, 1.0, 0.0],
+            Transform::Rotate180 => [-1.0, 0.0, 0.0, -1.0],
+            Transform::Rotate270 => [0.0, 1.0, -1.0, 0.0],
+            Transform::Flipped => [-1.0, 0.0, 0.0, 1.0],
+            Transform::Flipped90 => [0.0, 1.0, 1.0, 0.0],
+            Transform::Flipped180 => [1.0, 0.0, 0.0, -1.0],
+            Transform::Flipped270 => [0.0, -1.0, -1.0, 0.0],
+        }
+    }
+
+    /// This transform followed by `next`
+    pub fn then(self, next: Transform) -> Self {
+        let (a, b) = (self.matrix(), next.matrix());
+        let product = [
+            b[0] * a[0] + b[2] * a[1],
+            b[1] * a[0] + b[3] * a[1],
+            b[0] * a[2] + b[2] * a[3],
+            b[1] * a[2] + b[3] * a[3],
+        ];
+        Self::ALL
+            .into_iter()
+            .find(|t| t.matrix() == product)
+            .unwrap_or_default()
+    }
+}
+
+/// Which part of the source is kept when a scale mode crops it
+#[derive(Debug, Clone, Copy, Default)]
+pub enum CropAnchor {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl CropAnchor {
+    /// Position of the kept region as fractions of the overflow: (0 = left, 0 = top)
+    pub fn factors(self) -> (f32, f32) {
+        match self {
+            CropAnchor::Center => (0.5, 0.5),
+            CropAnchor::Top => (0.5, 0.0),
+            CropAnchor::Bottom => (0.5, 1.0),
+            CropAnchor::Left => (0.0, 0.5),
+            CropAnchor::Right => (1.0, 0.5),
+            CropAnchor::TopLeft => (0.0, 0.0),
+            CropAnchor::TopRight => (1.0, 0.0),
+            CropAnchor::BottomLeft => (0.0, 1.0),
+            CropAnchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROTATIONS: [(Transform, Transform); 4] = [
+        (Transform::Normal, Transform::Flipped),
+        (Transform::Rotate90, Transform::Flipped90),
+        (Transform::Rotate180, Transform::Flipped180),
+        (Transform::Rotate270, Transform::Flipped270),
+    ];
+
+    /// Where a clip-space point ends up under a transform
+    fn apply(transform: Transform, (x, y): (f32, f32)) -> (f32, f32) {
+        let m = transform.matrix();
+        (m[0] * x + m[2] * y, m[1] * x + m[3] * y)
+    }
+
+    #[test]
+    fn rotations_turn_clockwise() {
+        // The right edge's midpoint moves to the bottom, the top and the left
+        assert_eq!(apply(Transform::Rotate90, (1.0, 0.0)), (0.0, -1.0));
+        assert_eq!(apply(Transform::Rotate180, (1.0, 0.0)), (-1.0, 0.0));
+        assert_eq!(apply(Transform::Rotate270, (1.0, 0.0)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn flipped_transforms_flip_then_rotate() {
+        for (rotation, flipped) in ROTATIONS {
+            assert_eq!(Transform::Flipped.then(rotation), flipped, "{:?}", rotation);
+            for point in [(1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+                assert_eq!(
+                    apply(flipped, point),
+                    apply(rotation, apply(Transform::Flipped, point)),
+                    "{:?} at {:?}",
+                    flipped,
+                    point
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        // Flipping after rotating mirrors the rotation the other way
+        assert_eq!(
+            Transform::Rotate90.then(Transform::Flipped),
+            Transform::Flipped270
+        );
+        assert_eq!(
+            Transform::Rotate90.then(Transform::Rotate180),
+            Transform::Rotate270
+        );
+        assert_eq!(
+            Transform::Rotate270.then(Transform::Rotate90),
+            Transform::Normal
+        );
+    }
+
+    #[test]
+    fn undoing_an_output_transform_turns_the_buffer_upright() {
+        for transform in [
+            wl_output::Transform::Normal,
+            wl_output::Transform::_90,
+            wl_output::Transform::_180,
+            wl_output::Transform::_270,
+        ] {
+            let undo = Transform::undoing(transform);
+            let rotations = ROTATIONS.iter().position(|(r, _)| *r == undo).unwrap();
+            // wl_output rotates counter-clockwise by the same amount
+            let counter_clockwise = [
+                Transform::Normal,
+                Transform::Rotate270,
+                Transform::Rotate180,
+                Transform::Rotate90,
+            ][rotations];
+            assert_eq!(undo.then(counter_clockwise), Transform::Normal);
+        }
+    }
+
+    #[test]
+    fn swaps_axes_agrees_with_the_matrix() {
+        for transform in Transform::ALL {
+            let m = transform.matrix();
+            let swaps = m[0] == 0.0 && m[3] == 0.0;
+            assert_eq!(transform.swaps_axes(), swaps, "{:?}", transform);
+        }
+    }
 }
@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Window the frame rate is counted over
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+/// Size of one font pixel, in buffer pixels
+const PIXEL: i32 = 3;
+/// Distance of the readout from the top-left corner, in buffer pixels
+const MARGIN: i32 = 8;
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+
+/// Frame rate and latency of one target, shown on it with --show-stats
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    /// When each frame of the last second was swapped
+    swaps: VecDeque<Instant>,
+    /// From requesting the last frame's capture to swapping it onto the target
+    latency: Duration,
+}
+
+impl FrameStats {
+    /// Count a frame that was just swapped onto the target
+    pub fn record(&mut self, requested_at: Instant) {
+        let now = Instant::now();
+        self.latency = now.saturating_duration_since(requested_at);
+        self.swaps.push_back(now);
+        while self
+            .swaps
+            .front()
+            .is_some_and(|&swap| now.duration_since(swap) > FPS_WINDOW)
+        {
+            self.swaps.pop_front();
+        }
+    }
+
+    /// The readout, e.g. `60 FPS 12.5 MS`
+    pub fn text(&self) -> String {
+        format!(
+            "{} FPS {:.1} MS",
+            self.swaps.len(),
+            self.latency.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Rows of a 3x5 glyph, top first, leftmost pixel in bit 2; unknown characters are blank
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        _ => [0; 5],
+    }
+}
+
+/// Fill a rectangle (GL, bottom-left origin) of the bound framebuffer
+unsafe fn fill(x: i32, y: i32, width: i32, height: i32, color: [f32; 4]) {
+    gl::Scissor(x, y, width, height);
+    gl::ClearColor(color[0], color[1], color[2], color[3]);
+    gl::Clear(gl::COLOR_BUFFER_BIT);
+}
+
+/// Draw `text` in the top-left corner of a `surface_height` tall framebuffer.
+///
+/// GLES2 has no text rendering, and the readout is tiny, so every font pixel
+/// is a scissored clear; no shader or texture state is touched.
+///
+/// # Safety
+/// A GL context must be current with the target framebuffer bound.
+pub unsafe fn draw_text(text: &str, surface_height: i32) {
+    let advance = (GLYPH_WIDTH + 1) * PIXEL;
+    let box_width = text.chars().count() as i32 * advance + PIXEL;
+    let box_height = (GLYPH_HEIGHT + 2) * PIXEL;
+    let top = surface_height - MARGIN;
+
+    gl::Enable(gl::SCISSOR_TEST);
+    fill(
+        MARGIN,
+        top - box_height,
+        box_width,
+        box_height,
+        [0.0, 0.0, 0.0, 1.0],
+    );
+    for (i, c) in text.chars().enumerate() {
+        let left = MARGIN + PIXEL + i as i32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            let y = top - (row as i32 + 2) * PIXEL;
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) != 0 {
+                    fill(left + column * PIXEL, y, PIXEL, PIXEL, [1.0, 1.0, 0.0, 1.0]);
+                }
+            }
+        }
+    }
+    gl::Disable(gl::SCISSOR_TEST);
+}
@@ -1,26 +1,50 @@
 use anyhow::{Context, Result};
 use khronos_egl as egl;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use wayland_client::{
-    protocol::{wl_compositor, wl_output, wl_surface},
+    protocol::{wl_callback, wl_compositor, wl_output, wl_region, wl_surface},
     Connection, Dispatch, Proxy, QueueHandle,
 };
 use wayland_egl::WlEglSurface;
+use wayland_protocols::wp::fractional_scale::v1::client::{
+    wp_fractional_scale_manager_v1, wp_fractional_scale_v1,
+};
+use wayland_protocols::wp::viewporter::client::{wp_viewport, wp_viewporter};
 use wayland_protocols_wlr::layer_shell::v1::client::{
     zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
     zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
 };
 
-use crate::render::EglContext;
+use crate::render::{EglContext, FrameStats, TargetParams};
 use crate::wayland::AppState;
 
+/// Draw anyway if a frame callback hasn't come after this long, e.g. while the
+/// surface was hidden, so a target can't stop updating for good
+const FRAME_CALLBACK_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Newtype wrapper for surface data
+#[derive(Clone)]
 pub struct SurfaceData {
+    pub output_name: String,
     pub configured: Arc<Mutex<bool>>,
     pub pending_size: Arc<Mutex<(u32, u32)>>,
+    /// Set when the compositor closed the layer surface
+    pub closed: Arc<Mutex<bool>>,
+    /// Scale the compositor prefers for the surface, in 120ths (wp_fractional_scale_v1)
+    pub preferred_scale: Arc<Mutex<Option<u32>>>,
+    /// Set by the frame callback: the target showed the last frame and wants the next
+    pub frame_ready: Arc<AtomicBool>,
 }
 
+/// Newtype wrapper for frame callback data
+pub struct FrameCallbackData(pub Arc<AtomicBool>);
+
 pub struct MirrorSurface {
+    pub output_name: String,
+    compositor: wl_compositor::WlCompositor,
+    qh: QueueHandle<AppState>,
     pub wl_surface: wl_surface::WlSurface,
     pub layer_surface: ZwlrLayerSurfaceV1,
     pub egl_surface: WlEglSurface,
@@ -29,13 +53,41 @@ pub struct MirrorSurface {
     pub height: u32,
     pub configured: Arc<Mutex<bool>>,
     pub pending_size: Arc<Mutex<(u32, u32)>>,
+    closed: Arc<Mutex<bool>>,
+    /// Integer scale of the target output; the buffer is this many times the surface size
+    pub pending_scale: Arc<Mutex<i32>>,
+    scale: i32,
+    /// Exact fractional scale in 120ths, overriding `scale` once the compositor sent one
+    preferred_scale: Arc<Mutex<Option<u32>>>,
+    fractional_scale: Option<u32>,
+    fractional_scale_object: Option<wp_fractional_scale_v1::WpFractionalScaleV1>,
+    frame_ready: Arc<AtomicBool>,
+    /// When the pending frame callback was requested
+    frame_requested_at: Option<Instant>,
+    /// False while the target display is powered off; rendering is skipped
+    pub enabled: bool,
+    /// Whether every pixel we draw is opaque (advertised via the opaque region)
+    pub opaque: bool,
+    pub params: TargetParams,
+    /// Frame rate and latency drawn onto the target (--show-stats)
+    pub stats: Option<FrameStats>,
+    /// Buffer is rendered at 1/divisor of the surface size and upscaled by the compositor
+    buffer_divisor: u32,
+    viewport: Option<wp_viewport::WpViewport>,
 }
 
 impl MirrorSurface {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        output_name: &str,
+        namespace: &str,
         compositor: &wl_compositor::WlCompositor,
         layer_shell: &ZwlrLayerShellV1,
         output: &wl_output::WlOutput,
+        viewporter: Option<&wp_viewporter::WpViewporter>,
+        fractional_scale_manager: Option<
+            &wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        >,
         egl_ctx: &EglContext,
         qh: &QueueHandle<AppState>,
         width: u32,
@@ -43,6 +95,17 @@ impl MirrorSurface {
     ) -> Result<Self> {
         let configured = Arc::new(Mutex::new(false));
         let pending_size = Arc::new(Mutex::new((width, height)));
+        let closed = Arc::new(Mutex::new(false));
+        let data = SurfaceData {
+            output_name: output_name.to_string(),
+            configured: configured.clone(),
+            pending_size: pending_size.clone(),
+            closed: closed.clone(),
+            preferred_scale: Arc::new(Mutex::new(None)),
+            frame_ready: Arc::new(AtomicBool::new(true)),
+        };
+        let preferred_scale = data.preferred_scale.clone();
+        let frame_ready = data.frame_ready.clone();
 
         // Create Wayland surface
         let wl_surface = compositor.create_surface(qh, ());
@@ -52,14 +115,17 @@ impl MirrorSurface {
             &wl_surface,
             Some(output),
             zwlr_layer_shell_v1::Layer::Overlay,
-            "sway-mirror".to_string(),
+            namespace.to_string(),
             qh,
-            SurfaceData {
-                configured: configured.clone(),
-                pending_size: pending_size.clone(),
-            },
+            data.clone(),
         );
 
+        // Fractional scales are applied through the viewport, so both are needed
+        let fractional_scale_object = match (fractional_scale_manager, viewporter) {
+            (Some(manager), Some(_)) => Some(manager.get_fractional_scale(&wl_surface, qh, data)),
+            _ => None,
+        };
+
         // Configure as fullscreen
         layer_surface.set_anchor(
             zwlr_layer_surface_v1::Anchor::Top
@@ -75,13 +141,44 @@ impl MirrorSurface {
         wl_surface.commit();
 
         // Create EGL surface using the object id
-        let egl_surface = WlEglSurface::new(wl_surface.id(), width as i32, height as i32)
+        let mut buffer_divisor = 1;
+        let mut egl_surface = WlEglSurface::new(wl_surface.id(), width as i32, height as i32)
             .context("Failed to create WlEglSurface")?;
 
         let egl_window_surface =
-            egl_ctx.create_window_surface(egl_surface.ptr() as egl::NativeWindowType)?;
+            match egl_ctx.create_window_surface(egl_surface.ptr() as egl::NativeWindowType) {
+                Ok(s) => s,
+                // Out of GPU memory: render at half size and let the compositor upscale
+                Err(e)
+                    if viewporter.is_some()
+                        && e.downcast_ref::<egl::Error>() == Some(&egl::Error::BadAlloc) =>
+                {
+                    eprintln!(
+                        "Warning: EGL_BAD_ALLOC for a {}x{} surface on {}, retrying at half size",
+                        width, height, output_name
+                    );
+                    buffer_divisor = 2;
+                    egl_surface = WlEglSurface::new(
+                        wl_surface.id(),
+                        (width / buffer_divisor).max(1) as i32,
+                        (height / buffer_divisor).max(1) as i32,
+                    )
+                    .context("Failed to create WlEglSurface")?;
+                    egl_ctx.create_window_surface(egl_surface.ptr() as egl::NativeWindowType)?
+                }
+                Err(e) => return Err(e),
+            };
 
-        Ok(Self {
+        let viewport = if buffer_divisor > 1 || fractional_scale_object.is_some() {
+            viewporter.map(|v| v.get_viewport(&wl_surface, qh, ()))
+        } else {
+            None
+        };
+
+        let surface = Self {
+            output_name: output_name.to_string(),
+            compositor: compositor.clone(),
+            qh: qh.clone(),
             wl_surface,
             layer_surface,
             egl_surface,
@@ -90,20 +187,127 @@ impl MirrorSurface {
             height,
             configured,
             pending_size,
-        })
+            closed,
+            pending_scale: Arc::new(Mutex::new(1)),
+            scale: 1,
+            preferred_scale,
+            fractional_scale: None,
+            fractional_scale_object,
+            frame_ready,
+            frame_requested_at: None,
+            enabled: true,
+            // Letterbox bars are opaque by default, so any scale mode covers the surface
+            opaque: true,
+            params: TargetParams::default(),
+            stats: None,
+            buffer_divisor,
+            viewport,
+        };
+        surface.update_opaque_region();
+        surface.update_viewport();
+
+        Ok(surface)
+    }
+
+    /// Pass pointer input through to whatever is beneath the mirror
+    pub fn set_click_through(&self, click_through: bool) {
+        if click_through {
+            // An empty region accepts no input at all
+            let region = self.compositor.create_region(&self.qh, ());
+            self.wl_surface.set_input_region(Some(&region));
+            region.destroy();
+        } else {
+            // None restores the default infinite input region
+            self.wl_surface.set_input_region(None);
+        }
+    }
+
+    /// Declare whether everything drawn is opaque, i.e. the background has no transparency
+    pub fn set_opaque(&mut self, opaque: bool) {
+        if opaque != self.opaque {
+            self.opaque = opaque;
+            self.update_opaque_region();
+        }
+    }
+
+    /// Let the compositor skip blending (and possibly use a plane) for opaque content
+    fn update_opaque_region(&self) {
+        if self.opaque {
+            let region = self.compositor.create_region(&self.qh, ());
+            region.add(0, 0, self.width as i32, self.height as i32);
+            self.wl_surface.set_opaque_region(Some(&region));
+            region.destroy();
+        } else {
+            self.wl_surface.set_opaque_region(None);
+        }
+    }
+
+    /// Size of the EGL buffer: the surface size times the output scale, reduced after a downgrade
+    pub fn buffer_size(&self) -> (u32, u32) {
+        // Fractional sizes are rounded halfway away from zero, as the protocol asks
+        let scaled = |size: u32| match self.fractional_scale {
+            Some(scale) => (size * scale + 60) / 120,
+            None => size * self.scale as u32,
+        };
+        (
+            (scaled(self.width) / self.buffer_divisor).max(1),
+            (scaled(self.height) / self.buffer_divisor).max(1),
+        )
+    }
+
+    /// Stretch a reduced-size or fractionally scaled buffer over the whole surface
+    fn update_viewport(&self) {
+        if let Some(viewport) = &self.viewport {
+            viewport.set_destination(self.width as i32, self.height as i32);
+        }
+    }
+
+    /// Replace the EGL window surface, e.g. after the context switched configs
+    pub fn recreate_egl_surface(&mut self, egl_ctx: &mut EglContext) -> Result<()> {
+        egl_ctx.destroy_surface(self.egl_window_surface);
+        self.egl_window_surface =
+            egl_ctx.create_window_surface(self.egl_surface.ptr() as egl::NativeWindowType)?;
+        Ok(())
     }
 
     pub fn is_configured(&self) -> bool {
         *self.configured.lock().unwrap()
     }
 
+    /// The compositor closed the layer surface, so nothing drawn on it is shown anymore
+    pub fn is_closed(&self) -> bool {
+        *self.closed.lock().unwrap()
+    }
+
+    /// Configured and not closed, so a frame can be drawn and committed
+    pub fn is_drawable(&self) -> bool {
+        self.is_configured() && !self.is_closed()
+    }
+
     pub fn resize_if_needed(&mut self) -> bool {
         let pending = *self.pending_size.lock().unwrap();
-        if pending.0 != self.width || pending.1 != self.height {
+        let scale = *self.pending_scale.lock().unwrap();
+        let fractional_scale = *self.preferred_scale.lock().unwrap();
+        if pending.0 != self.width
+            || pending.1 != self.height
+            || scale != self.scale
+            || fractional_scale != self.fractional_scale
+        {
             self.width = pending.0;
             self.height = pending.1;
+            self.fractional_scale = fractional_scale;
+            if scale != self.scale {
+                self.scale = scale;
+                // With a viewport, its destination already sets the surface size
+                if self.viewport.is_none() {
+                    self.wl_surface.set_buffer_scale(scale);
+                }
+            }
+            let (buffer_width, buffer_height) = self.buffer_size();
             self.egl_surface
-                .resize(self.width as i32, self.height as i32, 0, 0);
+                .resize(buffer_width as i32, buffer_height as i32, 0, 0);
+            self.update_opaque_region();
+            self.update_viewport();
             true
         } else {
             false
@@ -113,11 +317,32 @@ impl MirrorSurface {
     pub fn commit(&self) {
         self.wl_surface.commit();
     }
+
+    /// Set `done` once the compositor wants the next frame after the upcoming commit
+    pub fn request_frame_callback(&self, done: Arc<AtomicBool>) {
+        self.wl_surface.frame(&self.qh, FrameCallbackData(done));
+    }
+
+    /// Whether the target is ready for a new frame: it showed the last one, or
+    /// its frame callback is overdue
+    pub fn is_ready_to_draw(&self) -> bool {
+        self.frame_ready.load(Ordering::SeqCst)
+            || self
+                .frame_requested_at
+                .is_none_or(|at| at.elapsed() >= FRAME_CALLBACK_TIMEOUT)
+    }
+
+    /// Wait for the frame callback of the upcoming commit before drawing again
+    pub fn throttle_to_next_frame(&mut self) {
+        self.frame_ready.store(false, Ordering::SeqCst);
+        self.frame_requested_at = Some(Instant::now());
+        self.request_frame_callback(self.frame_ready.clone());
+    }
 }
 
 impl Dispatch<ZwlrLayerSurfaceV1, SurfaceData> for AppState {
     fn event(
-        _state: &mut Self,
+        state: &mut Self,
         surface: &ZwlrLayerSurfaceV1,
         event: zwlr_layer_surface_v1::Event,
         data: &SurfaceData,
@@ -136,8 +361,10 @@ impl Dispatch<ZwlrLayerSurfaceV1, SurfaceData> for AppState {
                 }
                 *data.configured.lock().unwrap() = true;
             }
+            // E.g. the output was disabled; the main loop replaces the surface
             zwlr_layer_surface_v1::Event::Closed => {
-                // Surface was closed
+                *data.closed.lock().unwrap() = true;
+                state.closed_surfaces.push(data.output_name.clone());
             }
             _ => {}
         }
@@ -156,9 +383,57 @@ impl Dispatch<wl_surface::WlSurface, ()> for AppState {
     }
 }
 
+impl Dispatch<wl_callback::WlCallback, FrameCallbackData> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        data: &FrameCallbackData,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            data.0.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, SurfaceData> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wp_fractional_scale_v1::WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        data: &SurfaceData,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            *data.preferred_scale.lock().unwrap() = Some(scale);
+        }
+    }
+}
+
+impl Dispatch<wl_region::WlRegion, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_region::WlRegion,
+        _event: wl_region::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
 impl Drop for MirrorSurface {
     fn drop(&mut self) {
         // Destroy layer surface first, then the wl_surface
+        if let Some(viewport) = &self.viewport {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = &self.fractional_scale_object {
+            fractional_scale.destroy();
+        }
         self.layer_surface.destroy();
         self.wl_surface.destroy();
     }
@@ -0,0 +1,376 @@
+use std::time::{Duration, Instant};
+
+use crate::render::{CropAnchor, ScaleMode, Transform};
+
+/// Size a `width` x `height` region of the source is laid out at on a target.
+///
+/// The buffer size is divided by `source_scale`, which is above 1 when the
+/// capture is reconciled with the source's output scale, so Center shows
+/// logical pixels 1:1. A rotated image is laid out turned, so Fit and Fill see
+/// its on-screen aspect ratio.
+pub fn layout_size(width: u32, height: u32, source_scale: f32, transform: Transform) -> (u32, u32) {
+    let layout_w = (width as f32 / source_scale).round() as u32;
+    let layout_h = (height as f32 / source_scale).round() as u32;
+    if transform.swaps_axes() {
+        (layout_h, layout_w)
+    } else {
+        (layout_w, layout_h)
+    }
+}
+
+/// The mode a `src_width` x `src_height` source is actually drawn in on a
+/// `width` x `height` target: Auto becomes Center or Fit, the rest stay as they are.
+pub fn resolve_mode(
+    scale_mode: ScaleMode,
+    src_width: u32,
+    src_height: u32,
+    width: i32,
+    height: i32,
+) -> ScaleMode {
+    match scale_mode {
+        ScaleMode::Auto if src_width as i32 <= width && src_height as i32 <= height => {
+            ScaleMode::Center
+        }
+        ScaleMode::Auto => ScaleMode::Fit,
+        mode => mode,
+    }
+}
+
+/// Compute the viewport (x, y, width, height) the source is drawn into.
+///
+/// All values are in target surface pixels; the source size is taken as-is.
+/// The anchor decides which part of the source stays visible in Fill mode, and
+/// where the image sits along the free axis in FitWidth/FitHeight.
+pub fn compute_viewport(
+    scale_mode: ScaleMode,
+    anchor: CropAnchor,
+    src_width: u32,
+    src_height: u32,
+    width: i32,
+    height: i32,
+) -> (i32, i32, i32, i32) {
+    let src_w = src_width as f32;
+    let src_h = src_height as f32;
+    let dst_w = width as f32;
+    let dst_h = height as f32;
+    let src_aspect = src_w / src_h;
+    let dst_aspect = dst_w / dst_h;
+
+    match scale_mode {
+        ScaleMode::Auto => {
+            let mode = resolve_mode(scale_mode, src_width, src_height, width, height);
+            compute_viewport(mode, anchor, src_width, src_height, width, height)
+        }
+        ScaleMode::Stretch => {
+            // Fill entire target, ignore aspect ratio
+            (0, 0, width, height)
+        }
+        ScaleMode::Fit => {
+            // Preserve aspect ratio, fit within target (letterbox/pillarbox)
+            if src_aspect > dst_aspect {
+                // Source is wider - letterbox (black bars top/bottom)
+                let vp_h = (dst_w / src_aspect) as i32;
+                let vp_y = (height - vp_h) / 2;
+                (0, vp_y, width, vp_h)
+            } else {
+                // Source is taller - pillarbox (black bars left/right)
+                let vp_w = (dst_h * src_aspect) as i32;
+                let vp_x = (width - vp_w) / 2;
+                (vp_x, 0, vp_w, height)
+            }
+        }
+        ScaleMode::Fill => {
+            // Preserve aspect ratio, fill target completely (crops edges)
+            let (anchor_x, anchor_y) = anchor.factors();
+            if src_aspect > dst_aspect {
+                // Source is wider - extend beyond left/right edges
+                let vp_w = (dst_h * src_aspect) as i32;
+                let vp_x = ((width - vp_w) as f32 * anchor_x) as i32;
+                (vp_x, 0, vp_w, height)
+            } else {
+                // Source is taller - extend beyond top/bottom edges
+                // (GL viewports are bottom-up, so keeping the top shifts the viewport down)
+                let vp_h = (dst_w / src_aspect) as i32;
+                let vp_y = ((height - vp_h) as f32 * (1.0 - anchor_y)) as i32;
+                (0, vp_y, width, vp_h)
+            }
+        }
+        ScaleMode::FitWidth => {
+            // Full width; the anchor places the image when its height doesn't match
+            let (_, anchor_y) = anchor.factors();
+            let vp_h = (dst_w / src_aspect) as i32;
+            let vp_y = ((height - vp_h) as f32 * (1.0 - anchor_y)) as i32;
+            (0, vp_y, width, vp_h)
+        }
+        ScaleMode::FitHeight => {
+            // Full height; the anchor places the image when its width doesn't match
+            let (anchor_x, _) = anchor.factors();
+            let vp_w = (dst_h * src_aspect) as i32;
+            let vp_x = ((width - vp_w) as f32 * anchor_x) as i32;
+            (vp_x, 0, vp_w, height)
+        }
+        ScaleMode::Center => {
+            // Display at 1:1 pixel ratio, centered (no scaling)
+            let vp_w = src_width as i32;
+            let vp_h = src_height as i32;
+            let vp_x = (width - vp_w) / 2;
+            let vp_y = (height - vp_h) / 2;
+            (vp_x, vp_y, vp_w, vp_h)
+        }
+    }
+}
+
+/// Animates the viewport from the previous scale mode's layout to the current one.
+///
+/// Only the mode and start time are tracked; both layouts are recomputed for each
+/// surface, so one transition serves targets of any size.
+pub struct ViewportTransition {
+    duration: Duration,
+    mode: Option<ScaleMode>,
+    from: Option<(ScaleMode, Instant)>,
+}
+
+impl ViewportTransition {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            mode: None,
+            from: None,
+        }
+    }
+
+    /// Note the mode about to be drawn, starting an animation if it changed
+    pub fn set_mode(&mut self, mode: ScaleMode) {
+        if let Some(previous) = self.mode.replace(mode) {
+            if previous != mode {
+                self.from = Some((previous, Instant::now()));
+            }
+        }
+    }
+
+    /// The current mode's viewport, interpolated from the previous mode's while animating
+    pub fn viewport(
+        &mut self,
+        anchor: CropAnchor,
+        src_width: u32,
+        src_height: u32,
+        width: i32,
+        height: i32,
+    ) -> (i32, i32, i32, i32) {
+        let mode = self.mode.unwrap_or_default();
+        let target = compute_viewport(mode, anchor, src_width, src_height, width, height);
+
+        let Some((from_mode, started)) = self.from else {
+            return target;
+        };
+        let t = started.elapsed().as_secs_f32() / self.duration.as_secs_f32();
+        if t >= 1.0 {
+            self.from = None;
+            return target;
+        }
+
+        let from = compute_viewport(from_mode, anchor, src_width, src_height, width, height);
+        // Ease out so the motion settles gently
+        let t = 1.0 - (1.0 - t) * (1.0 - t);
+        let lerp = |a: i32, b: i32| a + ((b - a) as f32 * t).round() as i32;
+        (
+            lerp(from.0, target.0),
+            lerp(from.1, target.1),
+            lerp(from.2, target.2),
+            lerp(from.3, target.3),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_2_source_is_centered_in_logical_pixels() {
+        // A 1920x1080 logical source captured at scale 2, shown on a scale-1 target
+        let (w, h) = layout_size(3840, 2160, 2.0, Transform::Normal);
+        assert_eq!((w, h), (1920, 1080));
+        let viewport = compute_viewport(ScaleMode::Center, CropAnchor::Center, w, h, 1920, 1080);
+        assert_eq!(viewport, (0, 0, 1920, 1080));
+    }
+
+    #[test]
+    fn unreconciled_scale_2_source_is_centered_in_buffer_pixels() {
+        let (w, h) = layout_size(3840, 2160, 1.0, Transform::Normal);
+        assert_eq!((w, h), (3840, 2160));
+        let viewport = compute_viewport(ScaleMode::Center, CropAnchor::Center, w, h, 1920, 1080);
+        assert_eq!(viewport, (-960, -540, 3840, 2160));
+    }
+
+    #[test]
+    fn rotated_layout_swaps_axes() {
+        assert_eq!(
+            layout_size(3840, 2160, 2.0, Transform::Rotate90),
+            (1080, 1920)
+        );
+        assert_eq!(
+            layout_size(1920, 1080, 1.0, Transform::Flipped270),
+            (1080, 1920)
+        );
+        assert_eq!(
+            layout_size(1920, 1080, 1.0, Transform::Rotate180),
+            (1920, 1080)
+        );
+    }
+
+    /// Fill on a square target: the x offset of a wide source and the y offset of
+    /// a tall one, each checked to still cover the whole target
+    fn fill_offsets(anchor: CropAnchor) -> (i32, i32) {
+        let (x, y, w, h) = compute_viewport(ScaleMode::Fill, anchor, 1920, 1080, 1000, 1000);
+        assert_eq!((y, h), (0, 1000));
+        assert!(
+            x <= 0 && x + w >= 1000,
+            "wide source leaves a gap: {} + {}",
+            x,
+            w
+        );
+        let wide_x = x;
+
+        let (x, y, w, h) = compute_viewport(ScaleMode::Fill, anchor, 1080, 1920, 1000, 1000);
+        assert_eq!((x, w), (0, 1000));
+        assert!(
+            y <= 0 && y + h >= 1000,
+            "tall source leaves a gap: {} + {}",
+            y,
+            h
+        );
+        (wide_x, y)
+    }
+
+    // The scaled-up source is 1777 pixels along its long side, overflowing by 777.
+    // GL viewports are bottom-up, so keeping the top means the lowest y.
+
+    #[test]
+    fn fill_anchor_center() {
+        assert_eq!(fill_offsets(CropAnchor::Center), (-388, -388));
+    }
+
+    #[test]
+    fn fill_anchor_top() {
+        assert_eq!(fill_offsets(CropAnchor::Top), (-388, -777));
+    }
+
+    #[test]
+    fn fill_anchor_bottom() {
+        assert_eq!(fill_offsets(CropAnchor::Bottom), (-388, 0));
+    }
+
+    #[test]
+    fn fill_anchor_left() {
+        assert_eq!(fill_offsets(CropAnchor::Left), (0, -388));
+    }
+
+    #[test]
+    fn fill_anchor_right() {
+        assert_eq!(fill_offsets(CropAnchor::Right), (-777, -388));
+    }
+
+    #[test]
+    fn fill_anchor_top_left() {
+        assert_eq!(fill_offsets(CropAnchor::TopLeft), (0, -777));
+    }
+
+    #[test]
+    fn fill_anchor_top_right() {
+        assert_eq!(fill_offsets(CropAnchor::TopRight), (-777, -777));
+    }
+
+    #[test]
+    fn fill_anchor_bottom_left() {
+        assert_eq!(fill_offsets(CropAnchor::BottomLeft), (0, 0));
+    }
+
+    #[test]
+    fn fill_anchor_bottom_right() {
+        assert_eq!(fill_offsets(CropAnchor::BottomRight), (-777, 0));
+    }
+
+    fn viewport(mode: ScaleMode, src: (u32, u32)) -> (i32, i32, i32, i32) {
+        compute_viewport(mode, CropAnchor::Center, src.0, src.1, 1920, 1080)
+    }
+
+    #[test]
+    fn fit_width_letterboxes_a_wide_source() {
+        assert_eq!(
+            viewport(ScaleMode::FitWidth, (2000, 500)),
+            (0, 300, 1920, 480)
+        );
+    }
+
+    #[test]
+    fn fit_width_overflows_a_tall_source_vertically() {
+        assert_eq!(
+            viewport(ScaleMode::FitWidth, (1000, 1000)),
+            (0, -420, 1920, 1920)
+        );
+    }
+
+    #[test]
+    fn fit_height_overflows_a_wide_source_horizontally() {
+        assert_eq!(
+            viewport(ScaleMode::FitHeight, (1000, 500)),
+            (-120, 0, 2160, 1080)
+        );
+    }
+
+    #[test]
+    fn fit_height_pillarboxes_a_tall_source() {
+        assert_eq!(
+            viewport(ScaleMode::FitHeight, (500, 1000)),
+            (690, 0, 540, 1080)
+        );
+    }
+
+    #[test]
+    fn auto_centers_a_source_of_the_target_size() {
+        assert_eq!(viewport(ScaleMode::Auto, (1920, 1080)), (0, 0, 1920, 1080));
+    }
+
+    #[test]
+    fn auto_fits_a_source_one_pixel_wider() {
+        assert_eq!(
+            viewport(ScaleMode::Auto, (1921, 1080)),
+            viewport(ScaleMode::Fit, (1921, 1080))
+        );
+    }
+
+    #[test]
+    fn auto_fits_a_source_one_pixel_taller() {
+        assert_eq!(
+            viewport(ScaleMode::Auto, (1920, 1081)),
+            viewport(ScaleMode::Fit, (1920, 1081))
+        );
+    }
+
+    #[test]
+    fn auto_centers_a_source_smaller_on_one_axis() {
+        assert_eq!(
+            viewport(ScaleMode::Auto, (1280, 1080)),
+            (320, 0, 1280, 1080)
+        );
+        assert_eq!(viewport(ScaleMode::Auto, (1920, 720)), (0, 180, 1920, 720));
+    }
+
+    #[test]
+    fn auto_fits_a_source_larger_on_one_axis_only() {
+        assert_eq!(
+            viewport(ScaleMode::Auto, (2560, 720)),
+            viewport(ScaleMode::Fit, (2560, 720))
+        );
+    }
+
+    #[test]
+    fn auto_resolves_to_the_mode_it_draws_in() {
+        let resolve = |mode, (w, h)| resolve_mode(mode, w, h, 1920, 1080);
+        assert_eq!(resolve(ScaleMode::Auto, (1920, 1080)), ScaleMode::Center);
+        assert_eq!(resolve(ScaleMode::Auto, (1921, 1080)), ScaleMode::Fit);
+        assert_eq!(resolve(ScaleMode::Fill, (640, 480)), ScaleMode::Fill);
+        assert_eq!(resolve(ScaleMode::Center, (3840, 2160)), ScaleMode::Center);
+    }
+}
@@ -0,0 +1,491 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use wayland_client::Connection;
+
+use crate::capture::{dump, snapshot, CapturedFrame};
+use crate::presentation::PresentationLog;
+use crate::render::{EglContext, MirrorSurface, ScaleMode, Transform};
+use crate::stream::RgbaStream;
+
+/// Creates a target surface; run wherever the EGL context lives
+pub type SurfaceBuilder = Box<dyn FnOnce(&EglContext) -> Result<MirrorSurface> + Send>;
+
+/// A render setting changed while running
+#[derive(Debug, Clone, Copy)]
+pub enum Setting {
+    ScaleMode(ScaleMode),
+    /// Transform of every target, including ones added later
+    Transform(Transform),
+}
+
+/// The GL context and the surfaces it draws captured frames onto
+pub struct Renderer {
+    pub egl_ctx: EglContext,
+    pub surfaces: Vec<MirrorSurface>,
+    pub scale_mode: ScaleMode,
+    /// On render failure, switch to the next EGL config and retry once
+    pub retry_egl_config: bool,
+    /// Commit all targets after drawing all of them, instead of after each one
+    pub coalesce_commits: bool,
+    /// Surface whose frame callbacks pace rendering, with its "callback fired" flag
+    pacing: Option<(usize, Arc<AtomicBool>)>,
+    /// Where to write the next rendered frame's raw bytes (--dump-first-frame)
+    pub dump_path: Option<PathBuf>,
+    pub presentation_log: Option<PresentationLog>,
+    pub stdout_stream: Option<RgbaStream>,
+    /// Directory for snapshots, and the flag set when one is requested (--snapshot-on-signal)
+    pub snapshots: Option<(PathBuf, Arc<AtomicBool>)>,
+    /// Transform set while running, replacing the one targets were created with
+    transform: Option<Transform>,
+}
+
+// SAFETY: the EGL handles are plain pointers that any thread may use as long as
+// the context is current on at most one thread at a time; the context is released
+// before a Renderer is moved to the render thread and only used there afterwards.
+// The Wayland proxies behind the surfaces are thread-safe.
+unsafe impl Send for Renderer {}
+
+impl Renderer {
+    pub fn new(
+        egl_ctx: EglContext,
+        surfaces: Vec<MirrorSurface>,
+        scale_mode: ScaleMode,
+        retry_egl_config: bool,
+    ) -> Self {
+        Self {
+            egl_ctx,
+            surfaces,
+            scale_mode,
+            retry_egl_config,
+            coalesce_commits: false,
+            pacing: None,
+            dump_path: None,
+            presentation_log: None,
+            stdout_stream: None,
+            snapshots: None,
+            transform: None,
+        }
+    }
+
+    /// Change a setting; the next frame is drawn with it even if unchanged
+    pub fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::ScaleMode(mode) => self.scale_mode = mode,
+            Setting::Transform(transform) => {
+                self.transform = Some(transform);
+                for surface in &mut self.surfaces {
+                    surface.params.transform = transform;
+                }
+            }
+        }
+    }
+
+    /// Render at most once per frame callback of the given surface
+    pub fn pace_to(&mut self, surface_index: usize) {
+        self.pacing = Some((surface_index, Arc::new(AtomicBool::new(true))));
+    }
+
+    /// Apply pending resizes, then draw the frame on every target
+    pub fn render(&mut self, frame: &CapturedFrame) -> Result<()> {
+        for surface in &mut self.surfaces {
+            surface.resize_if_needed();
+        }
+
+        // Frames dropped by pacing below still count towards the blur
+        self.accumulate(frame)?;
+
+        // Drop frames until the pacing surface has shown the last one. A surface
+        // that is powered off never gets callbacks, so it can't hold us back.
+        if let Some((index, ref ready)) = self.pacing {
+            if let Some(surface) = self.surfaces.get(index).filter(|s| s.enabled) {
+                if !ready.swap(false, Ordering::SeqCst) {
+                    return Ok(());
+                }
+                surface.request_frame_callback(ready.clone());
+            }
+        }
+
+        if let Some(path) = self.dump_path.take() {
+            match dump::write_frame(frame, &path, &mut self.egl_ctx) {
+                Ok(()) => println!("Wrote first frame to {}", path.display()),
+                Err(e) => eprintln!("Warning: Failed to dump frame: {:#}", e),
+            }
+        }
+
+        if let Some(ref mut stream) = self.stdout_stream {
+            stream.write_frame(frame, &mut self.egl_ctx)?;
+        }
+
+        let result = self.render_to_surfaces(frame);
+        if let Err(ref e) = result {
+            if self.retry_egl_config && self.egl_ctx.switch_to_next_config()? {
+                eprintln!(
+                    "Warning: {}; retrying with EGL config {}/{}",
+                    e,
+                    self.egl_ctx.config_index + 1,
+                    self.egl_ctx.configs.len()
+                );
+                for surface in &mut self.surfaces {
+                    surface.recreate_egl_surface(&mut self.egl_ctx)?;
+                }
+                return self.render_to_surfaces(frame);
+            }
+        }
+        result
+    }
+
+    /// Blend a frame into the motion blur average without drawing it, if blur is on
+    pub fn accumulate(&mut self, frame: &CapturedFrame) -> Result<()> {
+        if self.egl_ctx.motion_blur > 1 {
+            self.egl_ctx.accumulate(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Start drawing onto a new target, e.g. an output that was plugged in
+    pub fn add_surface(&mut self, build: SurfaceBuilder) {
+        match build(&self.egl_ctx) {
+            Ok(mut surface) => {
+                if let Some(transform) = self.transform {
+                    surface.params.transform = transform;
+                }
+                self.surfaces.push(surface);
+            }
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
+
+    /// Pause drawing on targets whose display is powered off, and resume on ones
+    /// powered back on. `power` lists outputs with whether they are on; targets
+    /// missing from it count as on.
+    pub fn set_power(&mut self, power: &[(String, bool)]) {
+        for surface in &mut self.surfaces {
+            let on = power
+                .iter()
+                .find(|(name, _)| *name == surface.output_name)
+                .is_none_or(|&(_, on)| on);
+            if on != surface.enabled {
+                println!(
+                    "{} powered {}",
+                    surface.output_name,
+                    if on { "on, resuming" } else { "off, pausing" }
+                );
+                surface.enabled = on;
+            }
+        }
+    }
+
+    /// Stop drawing onto an output, e.g. because it was unplugged, and destroy its surface
+    pub fn remove_surface(&mut self, output_name: &str) -> Result<()> {
+        let Some(index) = self
+            .surfaces
+            .iter()
+            .position(|s| s.output_name == output_name)
+        else {
+            return Ok(());
+        };
+        // The EGL surface may be current, and must not outlive its window
+        self.egl_ctx.make_current_surfaceless()?;
+        let surface = self.surfaces.remove(index);
+        self.egl_ctx.destroy_surface(surface.egl_window_surface);
+
+        self.pacing = match self.pacing.take() {
+            Some((i, _)) if i == index => {
+                println!("Stopped pacing renders to {}", output_name);
+                None
+            }
+            Some((i, ready)) if i > index => Some((i - 1, ready)),
+            pacing => pacing,
+        };
+        Ok(())
+    }
+
+    /// Blank every enabled target, leaving its background color until the next frame
+    pub fn clear(&mut self) -> Result<()> {
+        for surface in self
+            .surfaces
+            .iter()
+            .filter(|s| s.enabled && s.is_drawable())
+        {
+            let (width, height) = surface.buffer_size();
+            self.egl_ctx.clear(
+                surface.egl_window_surface,
+                width as i32,
+                height as i32,
+                surface.params.background,
+            )?;
+            surface.commit();
+        }
+        Ok(())
+    }
+
+    /// Whether a snapshot is waiting for the next drawn frame
+    fn snapshot_requested(&self) -> bool {
+        self.snapshots
+            .as_ref()
+            .is_some_and(|(_, requested)| requested.load(Ordering::SeqCst))
+    }
+
+    fn render_to_surfaces(&mut self, frame: &CapturedFrame) -> Result<()> {
+        // Import once, then only the per-target drawing repeats
+        self.egl_ctx.bind_frame(frame)?;
+        // A snapshot saves what each target shows, named after it if there are several
+        let snapshot_dir = self
+            .snapshot_requested()
+            .then(|| self.snapshots.as_ref().map(|(dir, _)| dir.clone()))
+            .flatten();
+        let name_targets = self.surfaces.len() > 1;
+        // Targets added at runtime can't be drawn on before their first configure,
+        // and closed ones are about to be replaced. Each target is drawn at its own
+        // refresh: one still showing the previous frame skips this one.
+        let mut drawn = Vec::new();
+        for (index, surface) in self
+            .surfaces
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, s)| s.enabled && s.is_drawable())
+        {
+            if !surface.is_ready_to_draw() {
+                continue;
+            }
+            drawn.push(index);
+            surface.throttle_to_next_frame();
+            if let Some(ref mut log) = self.presentation_log {
+                log.track(&surface.wl_surface, &surface.output_name, frame.ready_at);
+            }
+            let (width, height) = surface.buffer_size();
+            let stats = surface.stats.as_ref().map(|stats| stats.text());
+            let pixels = self.egl_ctx.draw_to(
+                surface.egl_window_surface,
+                width as i32,
+                height as i32,
+                self.scale_mode,
+                &surface.params,
+                stats.as_deref(),
+                snapshot_dir.is_some(),
+            )?;
+            if let (Some(dir), Some(pixels)) = (&snapshot_dir, pixels) {
+                let target = name_targets.then_some(surface.output_name.as_str());
+                save_snapshot(
+                    snapshot::timestamped_path(dir, target),
+                    width,
+                    height,
+                    pixels,
+                );
+            }
+            if let Some(ref mut stats) = surface.stats {
+                stats.record(frame.requested_at);
+            }
+            if !self.coalesce_commits {
+                surface.commit();
+            }
+        }
+
+        // With no target drawn, the request waits for the next frame
+        if snapshot_dir.is_some() && !drawn.is_empty() {
+            if let Some((_, requested)) = &self.snapshots {
+                requested.store(false, Ordering::SeqCst);
+            }
+        }
+
+        // Commit every target back to back, so one flush carries them all
+        if self.coalesce_commits {
+            for index in drawn {
+                self.surfaces[index].commit();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encode and write a snapshot off the render thread, so the mirror keeps its cadence
+fn save_snapshot(path: PathBuf, width: u32, height: u32, pixels: Vec<u8>) {
+    let spawned =
+        thread::Builder::new().name("snapshot".to_string()).spawn(
+            move || match snapshot::write_png(&path, width, height, &pixels) {
+                Ok(()) => println!("Wrote snapshot to {}", path.display()),
+                Err(e) => eprintln!("Warning: Failed to write snapshot: {:#}", e),
+            },
+        );
+    if let Err(e) = spawned {
+        eprintln!("Warning: Failed to start snapshot writer: {}", e);
+    }
+}
+
+/// Work handed to the render thread
+pub enum Job {
+    Render(CapturedFrame),
+    Accumulate(CapturedFrame),
+    Clear,
+    AddSurface(SurfaceBuilder),
+    RemoveSurface(String),
+    Apply(Setting),
+    SetPower(Vec<(String, bool)>),
+}
+
+/// Renders frames either on the calling thread or on a dedicated render thread
+pub enum RenderLoop {
+    Inline(Box<Renderer>),
+    Threaded {
+        jobs: SyncSender<Job>,
+        /// Taken once the thread has been joined
+        handle: Option<JoinHandle<Result<Renderer>>>,
+    },
+}
+
+impl RenderLoop {
+    /// Move rendering to its own thread, which owns the EGL context from now on.
+    ///
+    /// Frames are handed over with their fds; the Wayland connection is only
+    /// flushed there, all event dispatch stays on the calling thread.
+    pub fn spawn(mut renderer: Renderer, connection: Connection) -> Result<Self> {
+        renderer.egl_ctx.release_current()?;
+
+        // One frame in flight: while the renderer is busy, newer captures are dropped
+        let (jobs, receiver) = mpsc::sync_channel::<Job>(1);
+        let handle = thread::Builder::new()
+            .name("render".to_string())
+            .spawn(move || {
+                while let Ok(job) = receiver.recv() {
+                    match job {
+                        Job::Render(frame) => renderer.render(&frame)?,
+                        Job::Accumulate(frame) => renderer.accumulate(&frame)?,
+                        Job::Clear => renderer.clear()?,
+                        Job::AddSurface(build) => renderer.add_surface(build),
+                        Job::RemoveSurface(name) => renderer.remove_surface(&name)?,
+                        Job::Apply(setting) => renderer.apply(setting),
+                        Job::SetPower(power) => renderer.set_power(&power),
+                    }
+                    connection.flush()?;
+                }
+                renderer.egl_ctx.release_current()?;
+                Ok(renderer)
+            })?;
+
+        Ok(Self::Threaded {
+            jobs,
+            handle: Some(handle),
+        })
+    }
+
+    /// Render a captured frame, or queue it for the render thread
+    pub fn submit(&mut self, frame: CapturedFrame) -> Result<()> {
+        match self {
+            Self::Inline(renderer) => renderer.render(&frame),
+            Self::Threaded { jobs, handle } => match jobs.try_send(Job::Render(frame)) {
+                Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+                // The thread only hangs up when rendering failed; report its error
+                Err(TrySendError::Disconnected(_)) => thread_error(handle),
+            },
+        }
+    }
+
+    /// Blend a frame into the motion blur average without drawing it
+    pub fn accumulate(&mut self, frame: CapturedFrame) -> Result<()> {
+        match self {
+            Self::Inline(renderer) => renderer.accumulate(&frame),
+            Self::Threaded { jobs, handle } => match jobs.try_send(Job::Accumulate(frame)) {
+                Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+                Err(TrySendError::Disconnected(_)) => thread_error(handle),
+            },
+        }
+    }
+
+    /// Blank all targets; unlike frames, this is never dropped
+    pub fn clear(&mut self) -> Result<()> {
+        match self {
+            Self::Inline(renderer) => renderer.clear(),
+            Self::Threaded { jobs, handle } => match jobs.send(Job::Clear) {
+                Ok(()) => Ok(()),
+                Err(_) => thread_error(handle),
+            },
+        }
+    }
+
+    /// Add a target surface, built where the EGL context lives; never dropped
+    pub fn add_surface(&mut self, build: SurfaceBuilder) -> Result<()> {
+        match self {
+            Self::Inline(renderer) => {
+                renderer.add_surface(build);
+                Ok(())
+            }
+            Self::Threaded { jobs, handle } => match jobs.send(Job::AddSurface(build)) {
+                Ok(()) => Ok(()),
+                Err(_) => thread_error(handle),
+            },
+        }
+    }
+
+    /// Remove the target surface on an output; never dropped
+    pub fn remove_surface(&mut self, output_name: &str) -> Result<()> {
+        match self {
+            Self::Inline(renderer) => renderer.remove_surface(output_name),
+            Self::Threaded { jobs, handle } => {
+                match jobs.send(Job::RemoveSurface(output_name.to_string())) {
+                    Ok(()) => Ok(()),
+                    Err(_) => thread_error(handle),
+                }
+            }
+        }
+    }
+
+    /// Change a render setting; never dropped
+    pub fn apply(&mut self, setting: Setting) -> Result<()> {
+        match self {
+            Self::Inline(renderer) => {
+                renderer.apply(setting);
+                Ok(())
+            }
+            Self::Threaded { jobs, handle } => match jobs.send(Job::Apply(setting)) {
+                Ok(()) => Ok(()),
+                Err(_) => thread_error(handle),
+            },
+        }
+    }
+
+    /// Pass on which outputs are powered on; never dropped
+    pub fn set_power(&mut self, power: Vec<(String, bool)>) -> Result<()> {
+        match self {
+            Self::Inline(renderer) => {
+                renderer.set_power(&power);
+                Ok(())
+            }
+            Self::Threaded { jobs, handle } => match jobs.send(Job::SetPower(power)) {
+                Ok(()) => Ok(()),
+                Err(_) => thread_error(handle),
+            },
+        }
+    }
+
+    /// Stop rendering and get the renderer back, e.g. to tear down its surfaces
+    pub fn finish(self) -> Result<Renderer> {
+        match self {
+            Self::Inline(renderer) => Ok(*renderer),
+            Self::Threaded { jobs, handle } => {
+                drop(jobs);
+                let handle = handle.ok_or_else(|| anyhow!("render thread stopped"))?;
+                let renderer = join(handle)?;
+                renderer.egl_ctx.make_current_surfaceless()?;
+                Ok(renderer)
+            }
+        }
+    }
+}
+
+/// The error the render thread stopped with
+fn thread_error(handle: &mut Option<JoinHandle<Result<Renderer>>>) -> Result<()> {
+    match handle.take() {
+        Some(handle) => join(handle).map(|_| ()),
+        None => Err(anyhow!("render thread stopped")),
+    }
+}
+
+fn join(handle: JoinHandle<Result<Renderer>>) -> Result<Renderer> {
+    handle
+        .join()
+        .map_err(|_| anyhow!("render thread panicked"))?
+}
@@ -0,0 +1,54 @@
+use std::time::{Duration, Instant};
+
+/// Running statistics over the intervals between successive events
+pub struct IntervalStats {
+    last: Option<Instant>,
+    count: u64,
+    mean: f64,
+    m2: f64,
+    max: Duration,
+}
+
+impl IntervalStats {
+    pub fn new() -> Self {
+        Self {
+            last: None,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            max: Duration::ZERO,
+        }
+    }
+
+    /// Record an event; the first one only establishes the baseline
+    pub fn record(&mut self, at: Instant) {
+        if let Some(last) = self.last.replace(at) {
+            let interval = at.saturating_duration_since(last);
+            let ms = interval.as_secs_f64() * 1000.0;
+
+            // Welford's online mean/variance
+            self.count += 1;
+            let delta = ms - self.mean;
+            self.mean += delta / self.count as f64;
+            self.m2 += delta * (ms - self.mean);
+
+            self.max = self.max.max(interval);
+        }
+    }
+
+    pub fn print_summary(&self, label: &str) {
+        if self.count == 0 {
+            println!("{}: not enough samples", label);
+            return;
+        }
+        let stddev = (self.m2 / self.count as f64).sqrt();
+        println!(
+            "{}: {} intervals, mean {:.2}ms, stddev {:.2}ms, max gap {:.2}ms",
+            label,
+            self.count,
+            self.mean,
+            stddev,
+            self.max.as_secs_f64() * 1000.0
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ScaleModeArg;
+
+/// What a running instance is doing, for `--status` and external tooling
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub pid: u32,
+    pub source: String,
+    pub targets: Vec<String>,
+    pub scale: ScaleModeArg,
+    pub cursor: bool,
+    /// Whether workspaces were moved to the source
+    pub workspaces: bool,
+    /// Unix timestamp (seconds) of when mirroring started
+    pub started_at: u64,
+}
+
+fn get_status_file_path() -> PathBuf {
+    // Lives next to the PID file
+    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+        return PathBuf::from(format!("{}/sway-mirror-status.json", dir));
+    }
+    if let Ok(dir) = std::env::var("XDG_STATE_HOME") {
+        return PathBuf::from(format!("{}/sway-mirror-status.json", dir));
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        return PathBuf::from(format!("{}/.local/state/sway-mirror-status.json", home));
+    }
+    PathBuf::from("/run/user/1000/sway-mirror-status.json")
+}
+
+impl SessionStatus {
+    pub fn new(
+        source: &str,
+        targets: Vec<String>,
+        scale: ScaleModeArg,
+        cursor: bool,
+        workspaces: bool,
+    ) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            pid: std::process::id(),
+            source: source.to_string(),
+            targets,
+            scale,
+            cursor,
+            workspaces,
+            started_at,
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self).context("Failed to serialize status")?;
+        fs::write(get_status_file_path(), json).context("Failed to write status file")?;
+        Ok(())
+    }
+
+    pub fn load() -> Result<Self> {
+        let json =
+            fs::read_to_string(get_status_file_path()).context("Failed to read status file")?;
+        serde_json::from_str(&json).context("Failed to parse status file")
+    }
+
+    pub fn remove() {
+        let _ = fs::remove_file(get_status_file_path());
+    }
+
+    pub fn uptime(&self) -> Duration {
+        let started = UNIX_EPOCH + Duration::from_secs(self.started_at);
+        SystemTime::now()
+            .duration_since(started)
+            .unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Format a duration as e.g. "1h 02m 03s"
+pub fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h {:02}m {:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
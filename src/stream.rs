@@ -0,0 +1,97 @@
+use anyhow::{bail, Result};
+use drm_fourcc::DrmFourcc;
+use nix::libc;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::FromRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::capture::CapturedFrame;
+use crate::render::EglContext;
+
+/// First bytes of the stream, so consumers can tell they are reading the right thing
+const MAGIC: &[u8; 8] = b"SWMRRGBA";
+
+/// Take over stdout for frame data, pointing fd 1 at stderr so all messages go there.
+///
+/// Must run before anything else prints, or messages end up in the stream.
+pub fn take_stdout() -> Result<File> {
+    io::stdout().flush()?;
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 {
+            bail!("Failed to duplicate stdout: {}", io::Error::last_os_error());
+        }
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            bail!("Failed to redirect stdout: {}", io::Error::last_os_error());
+        }
+        Ok(File::from_raw_fd(fd))
+    }
+}
+
+/// Raw RGBA frames written to the original stdout (--stdout).
+///
+/// Byte layout, integers little-endian:
+/// - once: magic `SWMRRGBA` (8 bytes), width (u32), height (u32), DRM fourcc (u32,
+///   always `AB24`, i.e. bytes R, G, B, A per pixel)
+/// - per frame: width * height * 4 bytes, rows top to bottom, no padding
+pub struct RgbaStream {
+    out: File,
+    /// Size announced in the header, once the first frame is written
+    size: Option<(u32, u32)>,
+    size_warned: bool,
+    /// Cleared when the reader goes away, to stop mirroring
+    running: Arc<AtomicBool>,
+}
+
+impl RgbaStream {
+    pub fn new(out: File, running: Arc<AtomicBool>) -> Self {
+        Self {
+            out,
+            size: None,
+            size_warned: false,
+            running,
+        }
+    }
+
+    /// Read the frame back through GL and write it, preceded by the header the first time
+    pub fn write_frame(&mut self, frame: &CapturedFrame, egl_ctx: &mut EglContext) -> Result<()> {
+        let size = (frame.width, frame.height);
+        match self.size {
+            Some(announced) if announced != size => {
+                // The header can't be changed after the fact, so drop these frames
+                if !self.size_warned {
+                    eprintln!(
+                        "Warning: Source is now {}x{}, not streaming frames until it is {}x{} again",
+                        size.0, size.1, announced.0, announced.1
+                    );
+                    self.size_warned = true;
+                }
+                return Ok(());
+            }
+            _ => self.size_warned = false,
+        }
+
+        let pixels = egl_ctx.read_back(frame)?;
+        let mut data = Vec::with_capacity(20 + pixels.len());
+        if self.size.is_none() {
+            data.extend_from_slice(MAGIC);
+            data.extend_from_slice(&size.0.to_le_bytes());
+            data.extend_from_slice(&size.1.to_le_bytes());
+            data.extend_from_slice(&(DrmFourcc::Abgr8888 as u32).to_le_bytes());
+            self.size = Some(size);
+        }
+        data.extend(pixels);
+
+        match self.out.write_all(&data) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                eprintln!("stdout closed, stopping");
+                self.running.store(false, Ordering::SeqCst);
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
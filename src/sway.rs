@@ -1,15 +1,480 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
-use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Retry IPC requests when sway can't be reached, e.g. during a config reload (--reconnect-sway)
+static RECONNECT: AtomicBool = AtomicBool::new(false);
+
+/// Refuse to talk to sway at all (--mirror-only)
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Attempts per IPC request when reconnecting; the wait doubles after each failure
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest wait for sway to answer a request
+const IPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest pause between power polls while sway fails to answer; the pause doubles
+/// after each failure
+const POWER_POLL_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Every IPC message starts with this, then the payload length and the message type
+const IPC_MAGIC: &[u8; 6] = b"i3-ipc";
+const IPC_HEADER_SIZE: usize = 14;
+
+/// i3/sway IPC message types
+const IPC_RUN_COMMAND: u32 = 0;
+const IPC_GET_WORKSPACES: u32 = 1;
+const IPC_GET_OUTPUTS: u32 = 3;
+const IPC_GET_TREE: u32 = 4;
+const IPC_SUBSCRIBE: u32 = 2;
+const IPC_GET_VERSION: u32 = 7;
+
+/// Events carry this bit in their message type, on top of the event type
+const IPC_EVENT_BIT: u32 = 1 << 31;
+const IPC_EVENT_WORKSPACE: u32 = IPC_EVENT_BIT;
+const IPC_EVENT_OUTPUT: u32 = IPC_EVENT_BIT | 1;
+
+pub fn set_reconnect(enabled: bool) {
+    RECONNECT.store(enabled, Ordering::SeqCst);
+}
+
+pub fn set_disabled(disabled: bool) {
+    DISABLED.store(disabled, Ordering::SeqCst);
+}
+
+/// Send one message to sway over its IPC socket and return the reply payload.
+///
+/// With reconnecting enabled, a failure is retried with backoff, each time
+/// against a freshly located IPC socket. Persistent failures are reported as a
+/// warning and the last error is returned.
+fn ipc_request(message_type: u32, payload: &str) -> Result<Vec<u8>> {
+    let mut result = ipc_request_once(message_type, payload);
+    if !RECONNECT.load(Ordering::SeqCst) {
+        return result;
+    }
+
+    let mut backoff = RECONNECT_BACKOFF;
+    for _ in 1..RECONNECT_ATTEMPTS {
+        if result.is_ok() {
+            return result;
+        }
+        thread::sleep(backoff);
+        backoff *= 2;
+        result = ipc_request_once(message_type, payload);
+    }
+    if let Err(ref e) = result {
+        eprintln!(
+            "Warning: Could not reach sway after {} attempts: {:#}",
+            RECONNECT_ATTEMPTS, e
+        );
+    }
+    result
+}
+
+fn ipc_request_once(message_type: u32, payload: &str) -> Result<Vec<u8>> {
+    let mut stream = connect()?;
+    stream.set_read_timeout(Some(IPC_TIMEOUT))?;
+
+    send_message(&mut stream, message_type, payload)?;
+    let (reply_type, reply) = read_message(&mut stream)?;
+    if reply_type != message_type {
+        bail!("Unexpected IPC reply from sway");
+    }
+    Ok(reply)
+}
+
+fn connect() -> Result<UnixStream> {
+    if DISABLED.load(Ordering::SeqCst) {
+        bail!("Sway IPC is disabled by --mirror-only");
+    }
+    let socket = find_ipc_socket()
+        .ok_or_else(|| anyhow::anyhow!("Sway IPC socket not found (is SWAYSOCK set?)"))?;
+    UnixStream::connect(&socket)
+        .with_context(|| format!("Failed to connect to sway at {}", socket.display()))
+}
+
+fn send_message(stream: &mut UnixStream, message_type: u32, payload: &str) -> Result<()> {
+    // The length and type are in native byte order
+    let mut message = Vec::with_capacity(IPC_HEADER_SIZE + payload.len());
+    message.extend_from_slice(IPC_MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream
+        .write_all(&message)
+        .context("Failed to send IPC request to sway")
+}
+
+/// Read one message: its type and payload
+fn read_message(stream: &mut UnixStream) -> Result<(u32, Vec<u8>)> {
+    let mut header = [0u8; IPC_HEADER_SIZE];
+    stream
+        .read_exact(&mut header)
+        .context("Failed to read IPC reply from sway")?;
+    if &header[..6] != IPC_MAGIC {
+        bail!("Malformed IPC message from sway");
+    }
+    let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+    let message_type = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+    let mut payload = vec![0u8; length as usize];
+    stream
+        .read_exact(&mut payload)
+        .context("Failed to read IPC reply from sway")?;
+    Ok((message_type, payload))
+}
+
+/// Send a query and parse its JSON reply
+fn ipc_query<T: serde::de::DeserializeOwned>(message_type: u32) -> Result<T> {
+    let reply = ipc_request(message_type, "")?;
+    serde_json::from_slice(&reply).context("Failed to parse sway's IPC reply")
+}
+
+/// Outcome of one command in a RUN_COMMAND request
+#[derive(Debug, Deserialize)]
+struct CommandOutcome {
+    success: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Run sway commands in a single RUN_COMMAND request, returning each one's outcome
+fn run_commands(commands: &[String]) -> Result<Vec<CommandOutcome>> {
+    let reply = ipc_request(IPC_RUN_COMMAND, &commands.join("; "))?;
+    serde_json::from_slice(&reply).context("Failed to parse sway's command reply")
+}
+
+/// The sway IPC socket: $SWAYSOCK if it still exists, otherwise the newest one in
+/// $XDG_RUNTIME_DIR (sway gets a new socket when it is restarted)
+fn find_ipc_socket() -> Option<PathBuf> {
+    if let Some(socket) = std::env::var_os("SWAYSOCK").map(PathBuf::from) {
+        if socket.exists() {
+            return Some(socket);
+        }
+    }
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("sway-ipc.") && name.ends_with(".sock")
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .map(|entry| entry.path())
+}
 
 #[derive(Debug, Deserialize)]
 struct SwayWorkspace {
     name: String,
     output: String,
     focused: bool,
+    /// Shown on its output (the focused one, or the one on another output)
+    #[serde(default)]
+    visible: bool,
+}
+
+/// Output as reported by GET_OUTPUTS
+#[derive(Debug, Deserialize)]
+pub struct SwayOutput {
+    pub name: String,
+    #[serde(default)]
+    pub active: bool,
+    /// Deprecated by sway 1.8 in favour of `power`, but still reported
+    #[serde(default = "default_true")]
+    pub dpms: bool,
+    #[serde(default)]
+    pub power: Option<bool>,
+    /// Position and size in sway's logical coordinate space
+    #[serde(default)]
+    pub rect: Option<SwayRect>,
+    #[serde(default)]
+    pub scale: Option<f64>,
+    #[serde(default)]
+    pub transform: Option<String>,
+    /// Whether the output holds the focused workspace
+    #[serde(default)]
+    pub focused: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SwayRect {
+    pub width: i32,
+    pub height: i32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl SwayOutput {
+    /// Whether the output is enabled and its display is powered on
+    pub fn is_on(&self) -> bool {
+        self.active && self.power.unwrap_or(self.dpms)
+    }
+
+    /// Size of the output's rect in buffer (device, untransformed) pixels
+    pub fn device_size(&self) -> Option<(u32, u32)> {
+        let rect = self.rect?;
+        let scale = self.scale.unwrap_or(1.0);
+        let width = (rect.width as f64 * scale).round() as u32;
+        let height = (rect.height as f64 * scale).round() as u32;
+        // The rect is rotated with the output, the exported buffer is not
+        match self.transform.as_deref() {
+            Some("90" | "270" | "flipped-90" | "flipped-270") => Some((height, width)),
+            _ => Some((width, height)),
+        }
+    }
+}
+
+/// Query sway for all outputs it knows about (including inactive ones)
+pub fn get_outputs() -> Result<Vec<SwayOutput>> {
+    ipc_query(IPC_GET_OUTPUTS)
+}
+
+fn get_workspaces() -> Result<Vec<SwayWorkspace>> {
+    ipc_query(IPC_GET_WORKSPACES)
+}
+
+/// Name of the output sway currently has focused
+pub fn focused_output() -> Result<String> {
+    get_outputs()?
+        .into_iter()
+        .find(|o| o.focused)
+        .map(|o| o.name)
+        .ok_or_else(|| anyhow::anyhow!("sway reports no focused output"))
+}
+
+/// Turn an output's display on or off, falling back to `dpms` for sway before 1.8
+pub fn set_output_power(output: &str, on: bool) -> Result<()> {
+    let state = if on { "on" } else { "off" };
+    let mut error = None;
+    for command in ["power", "dpms"] {
+        let outcomes = run_commands(&[format!("output {} {} {}", output, command, state)])?;
+        match outcomes.into_iter().find(|o| !o.success) {
+            None => return Ok(()),
+            Some(failed) => error = failed.error,
+        }
+    }
+    bail!(
+        "Failed to turn {} {}: {}",
+        output,
+        state,
+        error.as_deref().unwrap_or("unknown error")
+    )
+}
+
+/// Node of the GET_TREE reply, reduced to what we look at
+#[derive(Debug, Deserialize)]
+struct SwayNode {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(rename = "type")]
+    node_type: String,
+    /// For outputs, the workspace they currently show
+    #[serde(default)]
+    current_workspace: Option<String>,
+    #[serde(default)]
+    nodes: Vec<SwayNode>,
+    #[serde(default)]
+    floating_nodes: Vec<SwayNode>,
+}
+
+/// Whether the workspace visible on an output holds any windows.
+///
+/// An output sway doesn't know about counts as having windows, so callers never
+/// pause on a lookup they can't make sense of.
+pub fn output_has_windows(output: &str) -> Result<bool> {
+    let tree: SwayNode = ipc_query(IPC_GET_TREE)?;
+    let Some(node) = tree
+        .nodes
+        .iter()
+        .find(|n| n.node_type == "output" && n.name.as_deref() == Some(output))
+    else {
+        return Ok(true);
+    };
+    let Some(workspace) = node.nodes.iter().find(|n| {
+        n.node_type == "workspace" && n.name.is_some() && n.name == node.current_workspace
+    }) else {
+        return Ok(true);
+    };
+    // Containers only exist around windows, so any child means content
+    Ok(!workspace.nodes.is_empty() || !workspace.floating_nodes.is_empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct SwayVersion {
+    human_readable: String,
+}
+
+/// Query the running sway's version string
+pub fn get_version() -> Result<String> {
+    let version: SwayVersion = ipc_query(IPC_GET_VERSION)?;
+    Ok(version.human_readable)
+}
+
+/// A change in sway's layout, reported by an event subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwayEvent {
+    /// An output was added, removed or reconfigured
+    Output,
+    /// A workspace was created on or moved to an output
+    Workspace,
+}
+
+#[derive(Deserialize)]
+struct WorkspaceEvent {
+    change: String,
+}
+
+#[derive(Deserialize)]
+struct SubscribeReply {
+    success: bool,
+}
+
+/// Subscription to sway's output and workspace events, read on a background thread
+pub struct SwayEvents {
+    events: Receiver<SwayEvent>,
+    stream: UnixStream,
+    closing: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SwayEvents {
+    pub fn subscribe() -> Result<Self> {
+        let mut stream = connect()?;
+        stream.set_read_timeout(Some(IPC_TIMEOUT))?;
+        send_message(&mut stream, IPC_SUBSCRIBE, r#"["output","workspace"]"#)?;
+        let (_, reply) = read_message(&mut stream)?;
+        let reply: SubscribeReply =
+            serde_json::from_slice(&reply).context("Failed to parse sway's subscribe reply")?;
+        if !reply.success {
+            bail!("Sway refused the event subscription");
+        }
+        // Events come whenever they happen; the thread is stopped by shutting the socket
+        stream.set_read_timeout(None)?;
+
+        let (sender, events) = mpsc::channel();
+        let closing = Arc::new(AtomicBool::new(false));
+        let mut reader = stream.try_clone()?;
+        let reader_closing = closing.clone();
+        let handle = thread::Builder::new()
+            .name("sway-events".into())
+            .spawn(move || loop {
+                let (event_type, payload) = match read_message(&mut reader) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        if !reader_closing.load(Ordering::SeqCst) {
+                            eprintln!("Warning: Lost sway's event subscription: {:#}", e);
+                        }
+                        return;
+                    }
+                };
+                let event = match event_type {
+                    IPC_EVENT_OUTPUT => SwayEvent::Output,
+                    // Focus and rename events leave workspaces where they are
+                    IPC_EVENT_WORKSPACE => match serde_json::from_slice::<WorkspaceEvent>(&payload)
+                    {
+                        Ok(e) if e.change == "init" || e.change == "move" => SwayEvent::Workspace,
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            })?;
+
+        Ok(Self {
+            events,
+            stream,
+            closing,
+            handle: Some(handle),
+        })
+    }
+
+    /// Events received since the last call, each kind reported once
+    pub fn take(&self) -> Vec<SwayEvent> {
+        let mut events = Vec::new();
+        for event in self.events.try_iter() {
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+impl Drop for SwayEvents {
+    fn drop(&mut self) {
+        // Unblocks the thread's read, which then ends
+        self.closing.store(true, Ordering::SeqCst);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Whether each output is powered on, polled on a background thread so a slow or
+/// missing sway never holds up the caller
+pub struct PowerPoll {
+    power: Receiver<Vec<(String, bool)>>,
+    /// Wakes the thread for an early poll; dropping it ends the thread
+    wake: Sender<()>,
+}
+
+impl PowerPoll {
+    /// Poll every `interval`, backing off while sway can't be reached
+    pub fn spawn(interval: Duration) -> Result<Self> {
+        let (sender, power) = mpsc::channel();
+        let (wake, woken) = mpsc::channel();
+        // Not joined: a request may be waiting on a hung sway for up to IPC_TIMEOUT
+        thread::Builder::new()
+            .name("sway-power".into())
+            .spawn(move || {
+                let mut wait = interval;
+                loop {
+                    match woken.recv_timeout(wait) {
+                        Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                    match get_outputs() {
+                        Ok(outputs) => {
+                            let power = outputs.iter().map(|o| (o.name.clone(), o.is_on()));
+                            if sender.send(power.collect()).is_err() {
+                                return;
+                            }
+                            wait = interval;
+                        }
+                        Err(_) => wait = (wait * 2).min(POWER_POLL_MAX_BACKOFF),
+                    }
+                }
+            })?;
+        Ok(Self { power, wake })
+    }
+
+    /// Poll right away, e.g. after an output changed
+    pub fn poll_now(&self) {
+        let _ = self.wake.send(());
+    }
+
+    /// The outputs with whether they are on, if a poll finished since the last call
+    pub fn take(&self) -> Option<Vec<(String, bool)>> {
+        self.power.try_iter().last()
+    }
 }
 
 /// Stores original workspace-to-output mapping for restoration
@@ -20,9 +485,28 @@ pub struct WorkspaceState {
     /// The workspace that was focused before mirroring started
     original_focused: Option<String>,
     source_output: String,
+    /// Workspaces left where they are (--keep-workspace), neither moved nor restored
+    #[serde(default)]
+    kept: Vec<String>,
+    /// Workspace names in sway's order (by output, then position on the output)
+    #[serde(default)]
+    original_order: Vec<String>,
+    /// The workspace shown on each output before mirroring started
+    #[serde(default)]
+    original_visible: Vec<String>,
+}
+
+/// State file location given with --state-file, overriding the default
+static STATE_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_state_file(path: PathBuf) {
+    let _ = STATE_FILE.set(path);
 }
 
 fn get_state_file_path() -> PathBuf {
+    if let Some(path) = STATE_FILE.get() {
+        return path.clone();
+    }
     // Use XDG_RUNTIME_DIR (per-user, proper permissions)
     // Falls back to XDG_STATE_HOME or ~/.local/state
     if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
@@ -38,27 +522,44 @@ fn get_state_file_path() -> PathBuf {
 }
 
 impl WorkspaceState {
-    /// Query sway for current workspace layout and move all to source output
-    pub fn capture_and_move_to_source(source_output: &str) -> Result<Self> {
+    /// Query sway for current workspace layout and move all but the `kept` ones to
+    /// the source output, warning about kept workspaces on `targets` (which the
+    /// mirror covers)
+    pub fn capture_and_move_to_source(
+        source_output: &str,
+        kept: &[String],
+        targets: &[String],
+    ) -> Result<Self> {
         // Get current workspace state
-        let output = Command::new("swaymsg")
-            .args(["-t", "get_workspaces"])
-            .output()
-            .context("Failed to run swaymsg")?;
+        let workspaces = get_workspaces()?;
 
-        if !output.status.success() {
-            anyhow::bail!(
-                "swaymsg failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
+        for name in kept {
+            match workspaces.iter().find(|ws| ws.name == *name) {
+                None => eprintln!("Warning: Workspace {} to keep doesn't exist", name),
+                Some(ws) if targets.contains(&ws.output) => eprintln!(
+                    "Warning: Keeping workspace {} on {}, where the mirror will cover it",
+                    name, ws.output
+                ),
+                Some(_) => {}
+            }
         }
 
-        let workspaces: Vec<SwayWorkspace> =
-            serde_json::from_slice(&output.stdout).context("Failed to parse swaymsg output")?;
+        let state = Self::capture(&workspaces, source_output, kept);
+        state.move_to_source(&workspaces)?;
 
+        // Save state to disk so it can be restored if process is killed
+        state.save_to_file()?;
+
+        Ok(state)
+    }
+
+    /// The state to restore `workspaces` to, all but the `kept` ones moving away
+    /// from their outputs
+    fn capture(workspaces: &[SwayWorkspace], source_output: &str, kept: &[String]) -> Self {
         // Store original mapping
         let original_mapping: HashMap<String, String> = workspaces
             .iter()
+            .filter(|ws| !kept.contains(&ws.name))
             .map(|ws| (ws.name.clone(), ws.output.clone()))
             .collect();
 
@@ -68,50 +569,91 @@ impl WorkspaceState {
             .find(|ws| ws.focused)
             .map(|ws| ws.name.clone());
 
-        let state = Self {
+        Self {
             original_mapping,
             original_focused,
             source_output: source_output.to_string(),
-        };
-
-        // Move all workspaces from other outputs to source
-        for ws in &workspaces {
-            if ws.output != source_output {
-                state.move_workspace(&ws.name, source_output)?;
-            }
+            kept: kept.to_vec(),
+            original_order: workspaces.iter().map(|ws| ws.name.clone()).collect(),
+            original_visible: workspaces
+                .iter()
+                .filter(|ws| ws.visible)
+                .map(|ws| ws.name.clone())
+                .collect(),
         }
+    }
 
-        // Refocus the originally focused workspace (moving changes focus)
-        if let Some(ref focused) = state.original_focused {
-            let _ = Command::new("swaymsg")
-                .arg(format!("workspace {}", focused))
-                .output();
-        }
+    /// Move every workspace to the source again, e.g. after the source was
+    /// reconnected; the original mapping to restore is kept
+    pub fn move_all_to_source(&self) -> Result<()> {
+        self.move_to_source(&get_workspaces()?)
+    }
 
-        // Save state to disk so it can be restored if process is killed
-        state.save_to_file()?;
+    fn move_to_source(&self, workspaces: &[SwayWorkspace]) -> Result<()> {
+        Self::move_workspaces(
+            &self.source_moves(workspaces),
+            self.original_focused.as_slice(),
+        )?;
+        Ok(())
+    }
 
-        Ok(state)
+    /// Moves of all workspaces on other outputs, but the kept ones, to the source
+    fn source_moves<'a>(&'a self, workspaces: &'a [SwayWorkspace]) -> Vec<(&'a str, &'a str)> {
+        workspaces
+            .iter()
+            .filter(|ws| ws.output != self.source_output && !self.kept.contains(&ws.name))
+            .map(|ws| (ws.name.as_str(), self.source_output.as_str()))
+            .collect()
+    }
+
+    /// Move workspaces that turned up on other outputs since (e.g. newly created
+    /// ones) to the source, keeping the current focus; returns how many moved
+    pub fn move_strays_to_source(&self) -> Result<usize> {
+        let workspaces = get_workspaces()?;
+        let moves = self.source_moves(&workspaces);
+        if moves.is_empty() {
+            return Ok(0);
+        }
+        let focused: Vec<&str> = workspaces
+            .iter()
+            .filter(|ws| ws.focused)
+            .map(|ws| ws.name.as_str())
+            .collect();
+        Self::move_workspaces(&moves, &focused)?;
+        Ok(moves.len())
     }
 
     /// Save workspace state to disk
     fn save_to_file(&self) -> Result<()> {
         let path = get_state_file_path();
         let json = serde_json::to_string(self).context("Failed to serialize workspace state")?;
-        fs::write(&path, json).context("Failed to write workspace state file")?;
+        // Write a temporary file and rename it over the old one, so a crash
+        // mid-write never leaves a truncated state file behind
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, json).context("Failed to write workspace state file")?;
+        fs::rename(&tmp, &path).context("Failed to replace workspace state file")?;
         Ok(())
     }
 
-    /// Load workspace state from disk
+    /// Load workspace state from disk; a corrupt file is reported and treated as absent
     pub fn load_from_file() -> Result<Option<Self>> {
         let path = get_state_file_path();
         if !path.exists() {
             return Ok(None);
         }
         let json = fs::read_to_string(&path).context("Failed to read workspace state file")?;
-        let state: Self =
-            serde_json::from_str(&json).context("Failed to parse workspace state file")?;
-        Ok(Some(state))
+        match serde_json::from_str(&json) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) => {
+                eprintln!(
+                    "Warning: Ignoring corrupt workspace state file {}: {}",
+                    path.display(),
+                    e
+                );
+                Ok(None)
+            }
+        }
     }
 
     /// Remove the state file
@@ -119,68 +661,254 @@ impl WorkspaceState {
         let _ = fs::remove_file(get_state_file_path());
     }
 
+    /// Whether a saved state file exists (i.e. nothing has restored it yet)
+    pub fn state_file_exists() -> bool {
+        get_state_file_path().exists()
+    }
+
     /// Restore workspaces from saved state file (used by --stop)
-    pub fn restore_from_file() -> Result<()> {
+    pub fn restore_from_file(deadline: Option<Instant>) -> Result<()> {
         if let Some(state) = Self::load_from_file()? {
-            state.restore()?;
-            Self::remove_state_file();
+            state.restore_within(deadline, true)?;
         }
+        // Also drops a corrupt file, which can never be restored
+        Self::remove_state_file();
         Ok(())
     }
 
-    /// Move a workspace to an output
-    fn move_workspace(&self, workspace: &str, output: &str) -> Result<()> {
-        // First focus the workspace, then move it
-        let cmd = format!(
-            "workspace {}; move workspace to output {}",
-            workspace, output
-        );
-
-        let result = Command::new("swaymsg")
-            .arg(&cmd)
-            .output()
-            .context("Failed to run swaymsg")?;
+    /// Commands moving workspaces to outputs, then switching to each of `focus` in
+    /// turn (moving changes focus)
+    fn move_commands<S: AsRef<str>>(moves: &[(&str, &str)], focus: &[S]) -> Vec<String> {
+        // Each workspace is focused first, then moved
+        let mut commands: Vec<String> = moves
+            .iter()
+            .flat_map(|(workspace, output)| {
+                [
+                    format!("workspace {}", workspace),
+                    format!("move workspace to output {}", output),
+                ]
+            })
+            .collect();
+        commands.extend(focus.iter().map(|ws| format!("workspace {}", ws.as_ref())));
+        commands
+    }
 
-        if !result.status.success() {
-            eprintln!(
-                "Warning: Failed to move workspace {} to {}: {}",
-                workspace,
-                output,
-                String::from_utf8_lossy(&result.stderr)
-            );
+    /// Move workspaces to outputs, then switch to each of `focus` in turn, all in
+    /// one IPC request; returns whether each move succeeded
+    fn move_workspaces<S: AsRef<str>>(moves: &[(&str, &str)], focus: &[S]) -> Result<Vec<bool>> {
+        let outcomes = run_commands(&Self::move_commands(moves, focus))?;
+        let mut moved = Vec::with_capacity(moves.len());
+        for ((workspace, output), outcome) in moves.iter().zip(outcomes.chunks(2)) {
+            let failed = outcome.iter().find(|o| !o.success);
+            if let Some(failed) = failed {
+                eprintln!(
+                    "Warning: Failed to move workspace {} to {}: {}",
+                    workspace,
+                    output,
+                    failed.error.as_deref().unwrap_or("unknown error")
+                );
+            }
+            moved.push(failed.is_none());
         }
-
-        Ok(())
+        Ok(moved)
     }
 
     /// Restore all workspaces to their original outputs
     pub fn restore(&self) -> Result<()> {
+        self.restore_within(None, false)
+    }
+
+    /// Restore workspaces, giving up once `deadline` passes
+    fn restore_within(&self, deadline: Option<Instant>, verbose: bool) -> Result<()> {
         // Get current workspace state to know what exists
-        let output = Command::new("swaymsg")
-            .args(["-t", "get_workspaces"])
-            .output()
-            .context("Failed to run swaymsg")?;
-
-        let current_workspaces: Vec<SwayWorkspace> =
-            serde_json::from_slice(&output.stdout).unwrap_or_default();
-
-        // Move workspaces back to their original outputs
-        for ws in &current_workspaces {
-            if let Some(original_output) = self.original_mapping.get(&ws.name) {
-                if original_output != &self.source_output && ws.output == self.source_output {
-                    // This workspace was moved, restore it
-                    self.move_workspace(&ws.name, original_output)?;
-                }
-            }
-        }
+        let current_workspaces = get_workspaces()?;
+        let (moves, focus) = self.restore_moves(&current_workspaces);
 
-        // Return focus to the originally focused workspace (from before mirroring started)
-        if let Some(ref ws) = self.original_focused {
-            let _ = Command::new("swaymsg")
-                .arg(format!("workspace {}", ws))
-                .output();
+        // Looking up the workspaces may have taken the time there was
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            bail!("Timed out before restoring {} workspaces", moves.len());
         }
 
+        // Move workspaces back to their original outputs and return focus to the
+        // workspace focused before mirroring started
+        let moved = Self::move_workspaces(&moves, &focus)?;
+        if verbose {
+            // Failures were already reported
+            for ((workspace, original_output), _) in
+                moves.iter().zip(moved).filter(|(_, moved)| *moved)
+            {
+                println!("  {} -> {}", workspace, original_output);
+            }
+        }
         Ok(())
     }
+
+    /// The moves returning `current_workspaces` to their original outputs, and the
+    /// workspaces to switch to afterwards
+    fn restore_moves<'a>(
+        &'a self,
+        current_workspaces: &'a [SwayWorkspace],
+    ) -> (Vec<(&'a str, &'a str)>, Vec<&'a str>) {
+        // Workspaces that were moved to the source and still live there
+        let mut moves: Vec<(&str, &str)> = current_workspaces
+            .iter()
+            .filter_map(|ws| {
+                let original_output = self.original_mapping.get(&ws.name)?;
+                (original_output != &self.source_output && ws.output == self.source_output)
+                    .then_some((ws.name.as_str(), original_output.as_str()))
+            })
+            .collect();
+        // A moved workspace goes after those already on the output (unless sway sorts
+        // it by number), so moving them in their original order rebuilds each output's
+        // order; workspaces from an older state file without the order keep theirs
+        moves.sort_by_key(|(workspace, _)| {
+            self.original_order
+                .iter()
+                .position(|name| name == workspace)
+                .unwrap_or(usize::MAX)
+        });
+
+        // Show each output's original workspace again, then the focused one
+        let focus: Vec<&str> = self
+            .original_visible
+            .iter()
+            .filter(|ws| Some(*ws) != self.original_focused.as_ref())
+            .filter(|ws| current_workspaces.iter().any(|c| c.name == **ws))
+            .chain(&self.original_focused)
+            .map(String::as_str)
+            .collect();
+        (moves, focus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Workspaces as GET_WORKSPACES reports them, from `(name, output, visible)`;
+    /// the first visible one is focused
+    fn workspaces(layout: &[(&str, &str, bool)]) -> Vec<SwayWorkspace> {
+        let mut focused = false;
+        let json: Vec<String> = layout
+            .iter()
+            .map(|(name, output, visible)| {
+                let focus = *visible && !focused;
+                focused |= focus;
+                format!(
+                    r#"{{"num":-1,"name":"{}","output":"{}","focused":{},"visible":{}}}"#,
+                    name, output, focus, visible
+                )
+            })
+            .collect();
+        serde_json::from_str(&format!("[{}]", json.join(","))).unwrap()
+    }
+
+    /// Commands restoring `current` to how `state` found it
+    fn restore_commands(state: &WorkspaceState, current: &[SwayWorkspace]) -> Vec<String> {
+        let (moves, focus) = state.restore_moves(current);
+        WorkspaceState::move_commands(&moves, &focus)
+    }
+
+    #[test]
+    fn capture_moves_everything_to_the_source() {
+        let before = workspaces(&[
+            ("1", "eDP-1", false),
+            ("2", "HDMI-A-1", true),
+            ("3", "HDMI-A-1", false),
+        ]);
+        let state = WorkspaceState::capture(&before, "eDP-1", &[]);
+        let moves = state.source_moves(&before);
+        assert_eq!(
+            WorkspaceState::move_commands(&moves, state.original_focused.as_slice()),
+            [
+                "workspace 2",
+                "move workspace to output eDP-1",
+                "workspace 3",
+                "move workspace to output eDP-1",
+                "workspace 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_returns_workspaces_in_their_original_order() {
+        let before = workspaces(&[
+            ("1", "eDP-1", true),
+            ("4", "HDMI-A-1", false),
+            ("2", "HDMI-A-1", true),
+            ("3", "HDMI-A-1", false),
+        ]);
+        let state = WorkspaceState::capture(&before, "eDP-1", &[]);
+        // sway sorts the moved workspaces by number on the source
+        let after = workspaces(&[
+            ("1", "eDP-1", true),
+            ("2", "eDP-1", false),
+            ("3", "eDP-1", false),
+            ("4", "eDP-1", false),
+        ]);
+        assert_eq!(
+            restore_commands(&state, &after),
+            [
+                "workspace 4",
+                "move workspace to output HDMI-A-1",
+                "workspace 2",
+                "move workspace to output HDMI-A-1",
+                "workspace 3",
+                "move workspace to output HDMI-A-1",
+                "workspace 2",
+                "workspace 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_shows_each_visible_workspace_before_the_focused_one() {
+        let before = workspaces(&[
+            ("web", "HDMI-A-1", true),
+            ("mail", "HDMI-A-1", false),
+            ("1", "DP-2", true),
+            ("2", "eDP-1", true),
+        ]);
+        let state = WorkspaceState::capture(&before, "eDP-1", &[]);
+        assert_eq!(state.original_focused.as_deref(), Some("web"));
+        let after = workspaces(&[
+            ("2", "eDP-1", false),
+            ("web", "eDP-1", true),
+            ("mail", "eDP-1", false),
+            ("1", "eDP-1", false),
+        ]);
+        let commands = restore_commands(&state, &after);
+        assert_eq!(
+            commands[commands.len() - 3..],
+            ["workspace 1", "workspace 2", "workspace web"]
+        );
+    }
+
+    #[test]
+    fn restore_skips_kept_new_and_closed_workspaces() {
+        let before = workspaces(&[
+            ("1", "eDP-1", true),
+            ("2", "HDMI-A-1", true),
+            ("3", "HDMI-A-1", false),
+            ("chat", "DP-2", true),
+        ]);
+        let state = WorkspaceState::capture(&before, "eDP-1", &["chat".to_string()]);
+        // 3 was closed and 5 opened while mirroring; chat stayed on DP-2
+        let after = workspaces(&[
+            ("1", "eDP-1", true),
+            ("2", "eDP-1", false),
+            ("5", "eDP-1", false),
+            ("chat", "DP-2", true),
+        ]);
+        assert_eq!(
+            restore_commands(&state, &after),
+            [
+                "workspace 2",
+                "move workspace to output HDMI-A-1",
+                "workspace 2",
+                "workspace chat",
+                "workspace 1",
+            ]
+        );
+    }
 }
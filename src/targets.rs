@@ -0,0 +1,202 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::sync::Arc;
+use wayland_client::protocol::{wl_compositor::WlCompositor, wl_output::WlOutput};
+use wayland_client::QueueHandle;
+use wayland_protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1;
+use wayland_protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+
+use crate::render::{self, EglContext, FrameStats, Lut1d, MirrorSurface, ScaleMode, Transform};
+use crate::wayland::outputs::OutputManager;
+use crate::wayland::{AppState, WaylandState};
+use crate::{temperature_for, TemperatureArg, TileArg};
+
+/// An output to draw on: name, proxy and mode size
+pub type TargetOutput = (String, WlOutput, u32, u32);
+
+/// How every target surface is set up, kept so targets can also be created
+/// after startup, when an output is plugged in
+pub struct TargetTemplate {
+    pub source_name: String,
+    pub namespace: Option<String>,
+    pub click_through: bool,
+    pub temperatures: Vec<TemperatureArg>,
+    pub transform: Transform,
+    pub background: [f32; 4],
+    pub tiles: Vec<TileArg>,
+    pub luts: Vec<(String, Arc<Lut1d>)>,
+    pub scale_modes: Vec<(String, ScaleMode)>,
+    pub show_stats: bool,
+    compositor: WlCompositor,
+    layer_shell: ZwlrLayerShellV1,
+    viewporter: Option<WpViewporter>,
+    fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    qh: QueueHandle<AppState>,
+}
+
+impl TargetTemplate {
+    /// Take the globals surfaces are created with; the settings start out neutral
+    pub fn new(state: &WaylandState, qh: QueueHandle<AppState>) -> Result<Self> {
+        let compositor = state
+            .compositor
+            .clone()
+            .ok_or_else(|| anyhow!("wl_compositor not available"))?;
+        let layer_shell = state
+            .layer_shell
+            .clone()
+            .ok_or_else(|| anyhow!("zwlr_layer_shell_v1 not available"))?;
+        Ok(Self {
+            source_name: String::new(),
+            namespace: None,
+            click_through: false,
+            temperatures: Vec::new(),
+            transform: Transform::Normal,
+            background: [0.0, 0.0, 0.0, 1.0],
+            tiles: Vec::new(),
+            luts: Vec::new(),
+            scale_modes: Vec::new(),
+            show_stats: false,
+            compositor,
+            layer_shell,
+            viewporter: state.viewporter.clone(),
+            fractional_scale_manager: state.fractional_scale_manager.clone(),
+            qh,
+        })
+    }
+
+    /// Create the layer surface mirroring the source onto one output
+    pub fn build(&self, target: &TargetOutput, egl_ctx: &EglContext) -> Result<MirrorSurface> {
+        let (name, wl_output, width, height) = target;
+        let namespace = self
+            .namespace
+            .clone()
+            .unwrap_or_else(|| format!("sway-mirror:{}->{}", self.source_name, name));
+        let mut surface = MirrorSurface::new(
+            name,
+            &namespace,
+            &self.compositor,
+            &self.layer_shell,
+            wl_output,
+            self.viewporter.as_ref(),
+            self.fractional_scale_manager.as_ref(),
+            egl_ctx,
+            &self.qh,
+            *width,
+            *height,
+        )
+        .map_err(|e| anyhow!("Failed to create surface for {}: {}", name, e))?;
+        surface.set_click_through(self.click_through);
+        let kelvin = temperature_for(&self.temperatures, name);
+        if kelvin != render::NEUTRAL_TEMPERATURE {
+            println!("Color temperature on {}: {}K", name, kelvin);
+        }
+        surface.params.color_gain = render::temperature_gain(kelvin);
+        surface.params.transform = self.transform;
+        surface.params.background = self.background;
+        surface.set_opaque(self.background[3] >= 1.0);
+        if let Some(tile) = self.tiles.iter().rev().find(|t| t.output == *name) {
+            surface.params.source_region = Some(tile.region);
+        }
+        if let Some((_, lut)) = self.luts.iter().rev().find(|(output, _)| output == name) {
+            println!("Calibration LUT on {}: {} entries", name, lut.entries.len());
+            surface.params.lut = Some(lut.clone());
+        }
+        if let Some((_, mode)) = self
+            .scale_modes
+            .iter()
+            .rev()
+            .find(|(output, _)| output == name)
+        {
+            surface.params.scale_mode = Some(*mode);
+        }
+        if self.show_stats {
+            surface.stats = Some(FrameStats::default());
+        }
+        Ok(surface)
+    }
+}
+
+/// Tracks which outputs are drawn on, to follow outputs being plugged in and out
+pub struct TargetTracker {
+    /// Outputs (by global name) that have been considered as targets already
+    seen: HashSet<u32>,
+    /// Global and name of each output drawn on
+    targets: Vec<(u32, String)>,
+}
+
+impl TargetTracker {
+    /// Start from the outputs present now; only outputs appearing later are added
+    pub fn new(outputs: &OutputManager, targets: &[TargetOutput]) -> Self {
+        Self {
+            seen: outputs.outputs.keys().copied().collect(),
+            targets: targets
+                .iter()
+                .filter_map(|(name, ..)| {
+                    Some((outputs.get_by_name(name)?.global_name, name.clone()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Names of the outputs drawn on
+    pub fn names(&self) -> Vec<String> {
+        self.targets.iter().map(|(_, name)| name.clone()).collect()
+    }
+
+    /// Targets whose output went away, which are forgotten.
+    ///
+    /// Outputs are matched by global, so one unplugged and plugged back in
+    /// between two checks is still replaced.
+    pub fn take_removed(&mut self, outputs: &OutputManager) -> Vec<String> {
+        let (kept, removed): (Vec<_>, Vec<_>) = self
+            .targets
+            .drain(..)
+            .partition(|(global, _)| outputs.outputs.contains_key(global));
+        self.targets = kept;
+        removed.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// Newly appeared outputs that `wanted` accepts, which are recorded as targets.
+    ///
+    /// An output is only considered once its name is known.
+    pub fn take_added(
+        &mut self,
+        outputs: &OutputManager,
+        wanted: impl Fn(&str) -> bool,
+    ) -> Vec<TargetOutput> {
+        let mut added = Vec::new();
+        for (global, output) in &outputs.outputs {
+            if output.name.is_empty() || !self.seen.insert(*global) || !wanted(&output.name) {
+                continue;
+            }
+            self.targets.push((*global, output.name.clone()));
+            added.extend(target_output(outputs, &output.name));
+        }
+        added
+    }
+
+    /// A current target's output, to create its surface again
+    pub fn get(&self, outputs: &OutputManager, name: &str) -> Option<TargetOutput> {
+        self.targets
+            .iter()
+            .any(|(_, n)| n == name)
+            .then(|| target_output(outputs, name))
+            .flatten()
+    }
+
+    /// Stop tracking a target, e.g. one that can't be drawn on
+    pub fn forget(&mut self, name: &str) {
+        self.targets.retain(|(_, n)| n != name);
+    }
+}
+
+fn target_output(outputs: &OutputManager, name: &str) -> Option<TargetOutput> {
+    let output = outputs.get_by_name(name)?;
+    Some((
+        output.name.clone(),
+        output.wl_output.clone(),
+        output.width.max(0) as u32,
+        output.height.max(0) as u32,
+    ))
+}
@@ -1,22 +1,47 @@
 use anyhow::{Context, Result};
+use nix::libc;
 use std::ops::{Deref, DerefMut};
 use wayland_client::{
-    protocol::{wl_compositor, wl_output, wl_registry},
+    protocol::{wl_buffer, wl_compositor, wl_output, wl_registry},
     Connection, Dispatch, EventQueue, QueueHandle,
 };
+use wayland_protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1;
+use wayland_protocols::wp::linux_dmabuf::zv1::client::{
+    zwp_linux_buffer_params_v1, zwp_linux_dmabuf_v1,
+};
+use wayland_protocols::wp::presentation_time::client::wp_presentation;
+use wayland_protocols::wp::viewporter::client::{wp_viewport, wp_viewporter};
 use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_manager_v1;
 use wayland_protocols_wlr::export_dmabuf::v1::client::zwlr_export_dmabuf_manager_v1;
+use wayland_protocols_wlr::gamma_control::v1::client::zwlr_gamma_control_manager_v1;
 use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1;
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1;
 
-use super::outputs::OutputManager;
+use super::outputs::{request_xdg_outputs, OutputManager};
 
 /// Global state for Wayland connection
 pub struct WaylandState {
     pub compositor: Option<wl_compositor::WlCompositor>,
     pub layer_shell: Option<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
     pub dmabuf_manager: Option<zwlr_export_dmabuf_manager_v1::ZwlrExportDmabufManagerV1>,
+    pub screencopy_manager: Option<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1>,
+    /// Used to share our own buffers with the compositor for screencopy
+    pub linux_dmabuf: Option<zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1>,
     pub xdg_output_manager: Option<zxdg_output_manager_v1::ZxdgOutputManagerV1>,
+    pub viewporter: Option<wp_viewporter::WpViewporter>,
+    /// Lets mirrors render at fractional scales such as 1.5 (needs the viewporter)
+    pub fractional_scale_manager:
+        Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
+    pub presentation: Option<wp_presentation::WpPresentation>,
+    /// Used to dim the source when it can't be powered off (--blank-source)
+    pub gamma_manager: Option<zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1>,
+    /// Clock used for presentation timestamps (a clockid_t), announced on bind
+    pub presentation_clock: u32,
     pub output_manager: OutputManager,
+    /// Every global the compositor advertised, as (interface, version)
+    pub globals: Vec<(String, u32)>,
+    /// Outputs whose mirror surface the compositor closed since this was last taken
+    pub closed_surfaces: Vec<String>,
 }
 
 impl WaylandState {
@@ -25,8 +50,17 @@ impl WaylandState {
             compositor: None,
             layer_shell: None,
             dmabuf_manager: None,
+            screencopy_manager: None,
+            linux_dmabuf: None,
             xdg_output_manager: None,
+            viewporter: None,
+            fractional_scale_manager: None,
+            presentation: None,
+            gamma_manager: None,
+            presentation_clock: libc::CLOCK_MONOTONIC as u32,
             output_manager: OutputManager::new(),
+            globals: Vec::new(),
+            closed_surfaces: Vec::new(),
         }
     }
 }
@@ -89,6 +123,23 @@ impl WaylandConnection {
         Ok(())
     }
 
+    /// Send queued requests without waiting for anything
+    pub fn flush(&self) -> Result<()> {
+        self.connection.flush()?;
+        Ok(())
+    }
+
+    /// Wait for events and dispatch them
+    pub fn blocking_dispatch(&mut self) -> Result<()> {
+        self.queue.blocking_dispatch(&mut self.state)?;
+        Ok(())
+    }
+
+    /// Whether the compositor advertised a global with this interface name
+    pub fn has_global(&self, interface: &str) -> bool {
+        self.state.globals.iter().any(|(name, _)| name == interface)
+    }
+
     pub fn queue_handle(&self) -> QueueHandle<AppState> {
         self.queue.handle()
     }
@@ -103,12 +154,22 @@ impl Dispatch<wl_registry::WlRegistry, ()> for AppState {
         _conn: &Connection,
         qh: &QueueHandle<Self>,
     ) {
+        if let wl_registry::Event::GlobalRemove { name } = event {
+            // Outputs are the only globals we expect to come and go (hotplug)
+            if let Some(output) = state.output_manager.remove_output(name) {
+                eprintln!("Output {} removed", output.name);
+            }
+            return;
+        }
+
         if let wl_registry::Event::Global {
             name,
             interface,
             version,
         } = event
         {
+            state.globals.push((interface.clone(), version));
+
             match interface.as_str() {
                 "wl_compositor" => {
                     state.compositor = Some(registry.bind(name, version.min(5), qh, ()));
@@ -119,12 +180,33 @@ impl Dispatch<wl_registry::WlRegistry, ()> for AppState {
                 "zwlr_export_dmabuf_manager_v1" => {
                     state.dmabuf_manager = Some(registry.bind(name, version.min(1), qh, ()));
                 }
+                "zwlr_screencopy_manager_v1" => {
+                    state.screencopy_manager = Some(registry.bind(name, version.min(3), qh, ()));
+                }
+                "zwp_linux_dmabuf_v1" => {
+                    state.linux_dmabuf = Some(registry.bind(name, version.min(3), qh, ()));
+                }
                 "zxdg_output_manager_v1" => {
                     state.xdg_output_manager = Some(registry.bind(name, version.min(3), qh, ()));
                 }
+                "wp_viewporter" => {
+                    state.viewporter = Some(registry.bind(name, version.min(1), qh, ()));
+                }
+                "wp_fractional_scale_manager_v1" => {
+                    state.fractional_scale_manager =
+                        Some(registry.bind(name, version.min(1), qh, ()));
+                }
+                "wp_presentation" => {
+                    state.presentation = Some(registry.bind(name, version.min(1), qh, ()));
+                }
+                "zwlr_gamma_control_manager_v1" => {
+                    state.gamma_manager = Some(registry.bind(name, version.min(1), qh, ()));
+                }
                 "wl_output" => {
                     let output: wl_output::WlOutput = registry.bind(name, version.min(4), qh, name);
                     state.output_manager.add_output(name, output);
+                    // Outputs announced after startup (hotplug) need their name too
+                    request_xdg_outputs(state, qh);
                 }
                 _ => {}
             }
@@ -169,6 +251,56 @@ impl Dispatch<zwlr_export_dmabuf_manager_v1::ZwlrExportDmabufManagerV1, ()> for
     }
 }
 
+impl Dispatch<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+        _event: zwlr_screencopy_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+// The format list is ignored: buffers are allocated in whatever format screencopy offers
+impl Dispatch<zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+        _event: zwp_linux_dmabuf_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1,
+        _event: zwp_linux_buffer_params_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+// Screencopy buffers are reused in turn, so releases need no bookkeeping
+impl Dispatch<wl_buffer::WlBuffer, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_buffer::WlBuffer,
+        _event: wl_buffer::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
 impl Dispatch<zxdg_output_manager_v1::ZxdgOutputManagerV1, ()> for AppState {
     fn event(
         _state: &mut Self,
@@ -180,3 +312,54 @@ impl Dispatch<zxdg_output_manager_v1::ZxdgOutputManagerV1, ()> for AppState {
     ) {
     }
 }
+
+impl Dispatch<wp_viewporter::WpViewporter, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wp_viewporter::WpViewporter,
+        _event: wp_viewporter::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        _event: wp_fractional_scale_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wp_viewport::WpViewport, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wp_viewport::WpViewport,
+        _event: wp_viewport::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wp_presentation::WpPresentation, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &wp_presentation::WpPresentation,
+        event: wp_presentation::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            state.presentation_clock = clk_id;
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::os::fd::AsFd;
+use std::sync::{Arc, Mutex};
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::{Connection, Dispatch, QueueHandle};
+use wayland_protocols_wlr::gamma_control::v1::client::{
+    zwlr_gamma_control_manager_v1::{self, ZwlrGammaControlManagerV1},
+    zwlr_gamma_control_v1::{self, ZwlrGammaControlV1},
+};
+
+use super::{AppState, WaylandState};
+
+#[derive(Default)]
+struct GammaState {
+    /// Entries per ramp, announced by the compositor
+    size: Option<u32>,
+    /// Another client controls the gamma, or the output has no gamma tables
+    failed: bool,
+}
+
+pub struct GammaData(Arc<Mutex<GammaState>>);
+
+/// Dims an output through its gamma tables. Gamma is applied at scanout, so
+/// captures of the output are unaffected. The compositor restores the original
+/// tables once this is dropped.
+pub struct GammaDimmer {
+    control: ZwlrGammaControlV1,
+    state: Arc<Mutex<GammaState>>,
+}
+
+impl GammaDimmer {
+    /// Take control of the output's gamma; `dim` works after the next roundtrip
+    pub fn new(
+        state: &WaylandState,
+        output: &WlOutput,
+        qh: &QueueHandle<AppState>,
+    ) -> Result<Self> {
+        let manager = state
+            .gamma_manager
+            .as_ref()
+            .ok_or_else(|| anyhow!("zwlr_gamma_control_manager_v1 not available"))?;
+        let gamma = Arc::new(Mutex::new(GammaState::default()));
+        let control = manager.get_gamma_control(output, qh, GammaData(gamma.clone()));
+        Ok(Self {
+            control,
+            state: gamma,
+        })
+    }
+
+    /// Scale every ramp down to `level` (0 is black, 1 unchanged)
+    pub fn dim(&self, level: f32) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        if state.failed {
+            bail!("The compositor refused gamma control of the output");
+        }
+        let Some(size) = state.size.filter(|&size| size > 1) else {
+            bail!("The output's gamma size is unknown");
+        };
+
+        // Red, green and blue ramps one after the other, as native-endian u16
+        let ramp: Vec<u8> = (0..size)
+            .flat_map(|i| {
+                let value = i as f32 / (size - 1) as f32 * level.clamp(0.0, 1.0);
+                ((value * u16::MAX as f32).round() as u16).to_ne_bytes()
+            })
+            .collect();
+        let fd = memfd_create(c"sway-mirror-gamma", MemFdCreateFlag::MFD_CLOEXEC)
+            .context("Failed to create gamma table")?;
+        let mut file = File::from(fd);
+        for _ in 0..3 {
+            file.write_all(&ramp)
+                .context("Failed to write gamma table")?;
+        }
+        file.rewind().context("Failed to write gamma table")?;
+        self.control.set_gamma(file.as_fd());
+        Ok(())
+    }
+}
+
+impl Drop for GammaDimmer {
+    fn drop(&mut self) {
+        self.control.destroy();
+    }
+}
+
+impl Dispatch<ZwlrGammaControlManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrGammaControlManagerV1,
+        _event: zwlr_gamma_control_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrGammaControlV1, GammaData> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrGammaControlV1,
+        event: zwlr_gamma_control_v1::Event,
+        data: &GammaData,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let mut gamma = data.0.lock().unwrap();
+        match event {
+            zwlr_gamma_control_v1::Event::GammaSize { size } => gamma.size = Some(size),
+            zwlr_gamma_control_v1::Event::Failed => gamma.failed = true,
+            _ => {}
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod connection;
+pub mod gamma;
 pub mod outputs;
 
-pub use connection::{AppState, WaylandConnection};
+pub use connection::{AppState, WaylandConnection, WaylandState};
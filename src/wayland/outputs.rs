@@ -1,10 +1,11 @@
+use serde::Serialize;
 use std::collections::HashMap;
-use wayland_client::{protocol::wl_output, Connection, Dispatch, QueueHandle};
+use wayland_client::{protocol::wl_output, Connection, Dispatch, Proxy, QueueHandle};
 use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_v1;
 
 use super::connection::AppState;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Output {
     pub name: String,        // e.g., "DP-7", "eDP-1"
     pub description: String, // e.g., "Philips PHL 276E8V"
@@ -14,8 +15,14 @@ pub struct Output {
     pub x: i32,
     pub y: i32,
     pub scale: i32,
+    /// How the compositor rotates/flips content for this output
+    #[serde(skip)]
+    pub transform: wl_output::Transform,
+    #[serde(skip)]
     pub wl_output: wl_output::WlOutput,
-    #[allow(dead_code)]
+    #[serde(skip)]
+    pub xdg_output: Option<zxdg_output_v1::ZxdgOutputV1>,
+    #[serde(skip)]
     pub global_name: u32,
 }
 
@@ -30,10 +37,20 @@ impl Output {
             x: 0,
             y: 0,
             scale: 1,
+            transform: wl_output::Transform::Normal,
             wl_output,
+            xdg_output: None,
             global_name,
         }
     }
+
+    /// Refresh rate in Hz, e.g. "60.0 Hz", or "unknown" if the mode didn't say
+    pub fn refresh_hz(&self) -> String {
+        if self.refresh <= 0 {
+            return "unknown".to_string();
+        }
+        format!("{:.1} Hz", self.refresh as f64 / 1000.0)
+    }
 }
 
 pub struct OutputManager {
@@ -52,6 +69,19 @@ impl OutputManager {
             .insert(global_name, Output::new(global_name, wl_output));
     }
 
+    /// Forget an output whose global went away and release its proxies
+    pub fn remove_output(&mut self, global_name: u32) -> Option<Output> {
+        let output = self.outputs.remove(&global_name)?;
+        if let Some(ref xdg_output) = output.xdg_output {
+            xdg_output.destroy();
+        }
+        // wl_output.release only exists since version 3
+        if output.wl_output.version() >= 3 {
+            output.wl_output.release();
+        }
+        Some(output)
+    }
+
     pub fn get_by_name(&self, name: &str) -> Option<&Output> {
         self.outputs.values().find(|o| o.name == name)
     }
@@ -59,6 +89,13 @@ impl OutputManager {
     pub fn list(&self) -> Vec<&Output> {
         self.outputs.values().collect()
     }
+
+    /// Outputs in row-major order of their logical position (top to bottom, then left to right)
+    pub fn list_by_position(&self) -> Vec<&Output> {
+        let mut outputs = self.list();
+        outputs.sort_by(|a, b| (a.y, a.x, &a.name).cmp(&(b.y, b.x, &b.name)));
+        outputs
+    }
 }
 
 // Handle wl_output events
@@ -83,6 +120,12 @@ impl Dispatch<wl_output::WlOutput, u32> for AppState {
                     output.height = height;
                     output.refresh = refresh;
                 }
+                wl_output::Event::Geometry {
+                    transform: wayland_client::WEnum::Value(transform),
+                    ..
+                } => {
+                    output.transform = transform;
+                }
                 wl_output::Event::Scale { factor } => {
                     output.scale = factor;
                 }
@@ -114,11 +157,9 @@ impl Dispatch<zxdg_output_v1::ZxdgOutputV1, u32> for AppState {
                     output.x = x;
                     output.y = y;
                 }
-                zxdg_output_v1::Event::Name { name } => {
-                    // xdg_output name takes precedence
-                    if !name.is_empty() {
-                        output.name = name;
-                    }
+                // xdg_output name takes precedence
+                zxdg_output_v1::Event::Name { name } if !name.is_empty() => {
+                    output.name = name;
                 }
                 _ => {}
             }
@@ -126,11 +167,15 @@ impl Dispatch<zxdg_output_v1::ZxdgOutputV1, u32> for AppState {
     }
 }
 
-/// Request xdg_output for all outputs to get their names
-pub fn request_xdg_outputs(state: &AppState, qh: &QueueHandle<AppState>) {
+/// Request xdg_output for all outputs that don't have one yet, to get their names
+pub fn request_xdg_outputs(state: &mut AppState, qh: &QueueHandle<AppState>) {
+    let state = &mut state.0;
     if let Some(ref manager) = state.xdg_output_manager {
-        for (global_name, output) in &state.output_manager.outputs {
-            manager.get_xdg_output(&output.wl_output, qh, *global_name);
+        for (global_name, output) in state.output_manager.outputs.iter_mut() {
+            if output.xdg_output.is_none() {
+                output.xdg_output =
+                    Some(manager.get_xdg_output(&output.wl_output, qh, *global_name));
+            }
         }
     }
 }